@@ -29,6 +29,10 @@ pub enum Sub {
 
 // meowstore db create -c db_create.toml -p ./db
 // meowstore db run -c db_run.toml -p ./db
+// meowstore db reindex -c db_run.toml -p ./db
+// meowstore db backup -c db_run.toml -p ./db --to ./backup
+// meowstore db restore -p ./db --from ./backup --reindex db_run.toml
+// meowstore db check -c db_run.toml -p ./db --repair
 #[derive(Subcommand, Debug)]
 #[command(version, about, long_about = None)]
 pub enum Db {
@@ -43,6 +47,49 @@ pub enum Db {
         config: PathBuf,
         #[arg(long, short)]
         path: PathBuf,
+    },
+    // Rebuilds the meilisearch indexes from the stored data.
+    Reindex {
+        #[arg(long, short)]
+        config: PathBuf,
+        #[arg(long, short)]
+        path: PathBuf,
+    },
+    // Copies the database into an empty folder. The database must not be running.
+    Backup {
+        #[arg(long, short)]
+        config: PathBuf,
+        #[arg(long, short)]
+        path: PathBuf,
+        #[arg(long)]
+        to: PathBuf,
+    },
+    // Creates a database from a backup, after checking it's complete.
+    Restore {
+        #[arg(long, short)]
+        path: PathBuf,
+        #[arg(long)]
+        from: PathBuf,
+        // Run config to rebuild the search index with, after restoring.
+        #[arg(long)]
+        reindex: Option<PathBuf>,
+    },
+    // Checks that the indexes and the search index agree with the stored data.
+    Check {
+        #[arg(long, short)]
+        config: PathBuf,
+        #[arg(long, short)]
+        path: PathBuf,
+        // Remove stale entries and add missing ones.
+        #[arg(long)]
+        repair: bool,
+    },
+    // Upgrades a database written by an older version to the current format.
+    Migrate {
+        #[arg(long, short)]
+        config: PathBuf,
+        #[arg(long, short)]
+        path: PathBuf,
     }
 }
 
@@ -5,4 +5,8 @@ pub struct Run {
     pub db_url: String,
     pub db_auth_key: Option<db::auth::Key>,
     pub initial_user: String,
+    #[serde(default)]
+    pub initial_studios: Vec<u64>,
+    #[serde(default)]
+    pub initial_forum_categories: Vec<u64>,
 }
\ No newline at end of file
@@ -1,15 +1,13 @@
+use std::path::PathBuf;
 use serde::{Serialize, Deserialize};
-pub use db::config::RequireAuth;
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Create {
-    
-}
+pub use db::config::{Create, RequireAuth, Search};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Run {
     pub addr: String,
-    pub meili_host: String,
-    pub meili_key: String,
+    pub search: Search,
     pub require_auth: RequireAuth,
+    // Directory for backups made with the `/admin/backup` endpoint.
+    #[serde(default)]
+    pub backup_dir: Option<PathBuf>,
 }
@@ -1,7 +1,7 @@
-use std::fmt::Display;
+use std::{fmt::Display, path::Path};
 
 use clap::Parser;
-use tokio::{fs::{self, File}, io::AsyncWriteExt};
+use tokio::{fs::{self, File}, io::AsyncWriteExt, sync::mpsc};
 
 mod args;
 mod db_config;
@@ -10,6 +10,11 @@ mod crawler_config;
 // cargo run -- gen-config -p "../cli_usage"
 // cargo run -- db create -c "../cli_usage/db_create.toml" -p "../cli_usage/db"
 // cargo run -- db run -c "../cli_usage/db_run.toml" -p "../cli_usage/db"
+// cargo run -- db reindex -c "../cli_usage/db_run.toml" -p "../cli_usage/db"
+// cargo run -- db backup -c "../cli_usage/db_run.toml" -p "../cli_usage/db" --to "../cli_usage/backup"
+// cargo run -- db restore -p "../cli_usage/db_restored" --from "../cli_usage/backup"
+// cargo run -- db check -c "../cli_usage/db_run.toml" -p "../cli_usage/db"
+// cargo run -- db migrate -c "../cli_usage/db_run.toml" -p "../cli_usage/db"
 // cargo run -- crawler run -c "../cli_usage/crawler.toml"
 
 enum DbCommand {
@@ -24,6 +29,50 @@ impl Display for DbCommand {
     }
 }
 
+async fn read_run_config(path: &Path) -> db_config::Run {
+    let config_str = fs::read_to_string(path).await.unwrap();
+    toml::from_str::<db_config::Run>(&config_str).unwrap()
+}
+
+// Whether the database was opened before, so it has stored data.
+fn was_run(path: &Path) -> bool {
+    match db::manifest::read(&path.join("db_data")) {
+        Ok(Some(manifest)) => !manifest.storages.is_empty(),
+        Ok(None) => panic!("There's no database in this folder."),
+        Err(e) => panic!("Invalid database manifest: {e:?}"),
+    }
+}
+
+async fn init(config: db_config::Run, path: &Path) -> db_http_server::Init {
+    match db_http_server::init_with_config(server_config(config, path)).await {
+        Ok(init) => init,
+        Err(db::OpenError::Locked) => panic!("The database is open in another process."),
+        Err(db::OpenError::FormatVersion { found }) => panic!(
+            "The database is at version {found}, run `meowstore db migrate` to upgrade it to version {}.",
+            db::FORMAT_VERSION,
+        ),
+        Err(db::OpenError::NoManifest) => panic!(
+            "There's no database in this folder, or it's from an older version and has to be migrated with `meowstore db migrate`."
+        ),
+        Err(db::OpenError::MissingStorage(file)) => panic!(
+            "The database file {file} is missing, restore the database from a backup."
+        ),
+        Err(e) => panic!("Can't open the database: {e:?}"),
+    }
+}
+
+fn server_config(config: db_config::Run, path: &Path) -> db_http_server::config::Run {
+    db_http_server::config::Run {
+        db: db::config::Root {
+            require_auth: config.require_auth,
+            search: config.search,
+        },
+        db_path: path.join("db_data"),
+        addr: config.addr.parse().unwrap(),
+        backup_dir: config.backup_dir,
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let args = args::Root::parse();
@@ -32,40 +81,21 @@ async fn main() {
         args::Sub::Db { command } => {
             match command {
                 args::Db::Create { config, path } => {
-                    fs::create_dir_all(&path).await.unwrap();
                     let config_str = fs::read_to_string(config).await.unwrap();
                     let config = toml::from_str::<db_config::Create>(&config_str).unwrap();
-                    
-                    fs::write(path.join("status"), "new".as_bytes()).await.unwrap();
-                    fs::write(path.join("create.toml"), config_str.as_bytes()).await.unwrap();
+
+                    match db::manifest::create(&path.join("db_data"), config) {
+                        Ok(_) => {},
+                        Err(db::manifest::CreateError::Exists) => panic!("A database already exists in this folder."),
+                        Err(e) => panic!("Can't create the database: {e:?}"),
+                    }
                 },
                 args::Db::Run { config, path } => {
-                    let config_str = fs::read_to_string(config).await.unwrap();
-                    let config = toml::from_str::<db_config::Run>(&config_str).unwrap();
-                    
-                    let status = fs::read_to_string(path.join("status")).await.unwrap();
-                    let create_config_str = fs::read_to_string(path.join("create.toml")).await.unwrap();
-                    let create_config = toml::from_str::<db_config::Create>(&create_config_str).unwrap();
-
-                    let mode = match status.as_str() {
-                        "new" => db_http_server::OpenMode::New,
-                        "existing" => db_http_server::OpenMode::Existing,
-                        _ => panic!("Invalid status file. The database folder is corrupted.")
-                    };
-                    fs::write(path.join("status"), "existing".as_bytes()).await.unwrap();
-
-                    println!("Running at {}", &config.addr);
-                    let addr = config.addr.parse().unwrap();
-                    let init = db_http_server::init_with_config(db_http_server::config::Run {
-                        mode,
-                        db: db::config::Root {
-                            require_auth: config.require_auth,
-                        },
-                        db_path: path.join("db_data"),
-                        addr,
-                        meili_addr: config.meili_host,
-                        meili_key: config.meili_key,
-                    }).await;
+                    let config = read_run_config(&config).await;
+
+                    let addr = config.addr.clone();
+                    let init = init(config, &path).await;
+                    println!("Running at {addr}");
 
                     {
                         let init = init.clone();
@@ -113,6 +143,103 @@ async fn main() {
                             }
                         }
                     }
+                },
+                args::Db::Reindex { config, path } => {
+                    let config = read_run_config(&config).await;
+                    if !was_run(&path) {
+                        panic!("The database was never run, there's nothing to reindex.")
+                    }
+
+                    let init = init(config, &path).await;
+                    init.db.read().await.reindex_search(|progress| {
+                        println!("Reindexed {} documents in \"{}\"", progress.done, progress.index);
+                    }).await.unwrap();
+                    println!("Done.");
+                },
+                args::Db::Backup { config, path, to } => {
+                    let config = read_run_config(&config).await;
+                    if !was_run(&path) {
+                        panic!("The database was never run, there's nothing to back up.")
+                    }
+
+                    let init = init(config, &path).await;
+                    let manifest = init.db.read().await.snapshot(&to).unwrap();
+                    println!("Copied {} files.", manifest.files.len());
+                },
+                args::Db::Restore { path, from, reindex } => {
+                    let manifest = match db::snapshot::restore(&from, &path.join("db_data")) {
+                        Ok(manifest) => manifest,
+                        Err(e) => panic!("Can't restore the backup: {e:?}"),
+                    };
+                    println!("Restored {} files.", manifest.files.len());
+
+                    if let Some(config) = reindex {
+                        let config = read_run_config(&config).await;
+                        let init = init(config, &path).await;
+                        init.db.read().await.reindex_search(|progress| {
+                            println!("Reindexed {} documents in \"{}\"", progress.done, progress.index);
+                        }).await.unwrap();
+                    }
+                    println!("Done.");
+                },
+                args::Db::Check { config, path, repair } => {
+                    let config = read_run_config(&config).await;
+                    if !was_run(&path) {
+                        panic!("The database was never run, there's nothing to check.")
+                    }
+
+                    let init = init(config, &path).await;
+                    let report = init.db.write().await.check(repair).await.unwrap();
+                    for issue in &report.issues {
+                        println!("{}: {:?}", issue.index, issue.kind);
+                    }
+                    match (report.issues.len(), report.repaired) {
+                        (0, _) => println!("No issues found."),
+                        (len, true) => println!("Repaired {len} issues."),
+                        (len, false) => println!("Found {len} issues, run with --repair to fix them."),
+                    }
+                },
+                args::Db::Migrate { config, path } => {
+                    let config = read_run_config(&config).await;
+                    if !fs::try_exists(path.join("db_data")).await.unwrap() {
+                        panic!("There's no database in this folder.")
+                    }
+                    // Databases from before the manifest kept the create config next to the data.
+                    let create = match fs::read_to_string(path.join("create.toml")).await {
+                        Ok(create_str) => toml::from_str::<db_config::Create>(&create_str).unwrap(),
+                        Err(_) => db_config::Create {},
+                    };
+
+                    let (error_sender, mut error_receiver) = mpsc::channel(20);
+                    let _error_handle = tokio::spawn(async move {
+                        while let Some(err) = error_receiver.recv().await {
+                            println!("[INTERNAL ERROR]: {err:?}");
+                        }
+                    });
+
+                    let config = server_config(config, &path);
+                    let migrated = match db::migrate::migrate(&config.db_path, create, config.db, error_sender).await {
+                        Ok(migrated) => migrated,
+                        Err(db::migrate::Error::Open(db::OpenError::Locked)) => panic!("The database is open in another process."),
+                        Err(db::migrate::Error::NameCollisions(collisions)) => panic!(
+                            "Users with names that only differ in case can't be migrated, remove all but one of each with the version that wrote the database: {collisions:?}"
+                        ),
+                        Err(e) => panic!("Can't migrate the database: {e:?}"),
+                    };
+                    // Replaced by the manifest.
+                    for legacy_file in ["status", "create.toml"] {
+                        if fs::try_exists(path.join(legacy_file)).await.unwrap() {
+                            fs::remove_file(path.join(legacy_file)).await.unwrap();
+                        }
+                    }
+                    if migrated.steps.is_empty() {
+                        println!("The database is already at version {}.", db::FORMAT_VERSION);
+                    }
+                    for (version, desc) in (migrated.from..).zip(&migrated.steps) {
+                        println!("{version} -> {}: {desc}", version + 1);
+                    }
+                    println!("Rebuilt {} index entries and search documents.", migrated.report.issues.len());
+                    println!("Done.");
                 }
             }
         },
@@ -120,8 +247,8 @@ async fn main() {
             let db_create = db_config::Create {};
             let db_run = db_config::Run {
                 addr: "127.0.0.1:3030".into(),
-                meili_host: "http://localhost:7700".into(),
-                meili_key: "aSampleMasterKey".into(),
+                search: db_config::Search::Embedded,
+                backup_dir: None,
                 require_auth: db_config::RequireAuth {
                     read: false,
                     write: false,
@@ -131,6 +258,8 @@ async fn main() {
             let crawler_run = crawler_config::Run {
                 db_url: "http://localhost:3030".into(),
                 initial_user: "griffpatch".into(),
+                initial_studios: Vec::new(),
+                initial_forum_categories: Vec::new(),
                 db_auth_key: None,
             };
 
@@ -154,6 +283,8 @@ async fn main() {
                     crawler::run_with_config(crawler::config::Run {
                         db_url: config.db_url,
                         initial_user: config.initial_user,
+                        initial_studios: config.initial_studios,
+                        initial_forum_categories: config.initial_forum_categories,
                         db_auth_key: config.db_auth_key,
                     }).await;
                 }
@@ -4,4 +4,6 @@ pub struct Run {
     pub db_url: String,
     pub db_auth_key: Option<db::auth::Key>,
     pub initial_user: String,
+    pub initial_studios: Vec<u64>,
+    pub initial_forum_categories: Vec<u64>,
 }
\ No newline at end of file
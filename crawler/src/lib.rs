@@ -4,10 +4,20 @@ use rs2s::{input::ItemsRange, Username};
 use http_input::{Bytes, Instance as HttpInput};
 pub mod config;
 
+// Number of items on a full page of the Scratch forums.
+const FORUM_TOPICS_PAGE_LEN: usize = 25;
+const FORUM_POSTS_PAGE_LEN: usize = 20;
+
 pub async fn run_with_config(config: config::Run) {
     let client = reqwest::Client::new();
     let mut state = State::new(client, &config.db_url, config.db_auth_key);
     state.request_queue.users.push(rs2s::input::User(Username::new(config.initial_user)));
+    for id in config.initial_studios {
+        state.request_queue.studios.push(rs2s::input::Studio(id));
+    }
+    for id in config.initial_forum_categories {
+        state.request_queue.forum_topics.push(rs2s::input::forum::Topics(id, 1));
+    }
     loop {
         state.request_respond().await.unwrap();
     }
@@ -21,7 +31,12 @@ pub enum RequestError {
 #[derive(Debug)]
 pub enum Error {
     ParseUsername,
-    ParseDateTime(chrono::ParseError)
+    ParseDateTime(chrono::ParseError),
+    WriteStudio(db_http_server::studios::WriteError),
+    WriteStudioList(db_http_server::studios::WriteListError),
+    WriteForumTopic(db_http_server::forum::TopicWriteError),
+    WriteForumPost(db_http_server::forum::PostWriteError),
+    WriteFollowers(db_http_server::users::WriteFollowersError),
 }
 
 #[derive(Debug)]
@@ -177,14 +192,17 @@ impl State {
 
     pub async fn request_user_followers(&mut self) -> Result<(), RequestError> {
         type E = RequestError;
-        if let Some(req) = self.request_queue.users_followers.pop() {
+        if let Some((user_id, req)) = self.request_queue.users_followers.pop() {
             let out = self.http_send(req).await.map_err(E::HttpSend)?;
             match out {
                 Ok(out) => {
+                    let mut follower_ids = Vec::with_capacity(out.len());
                     for user in out {
+                        follower_ids.push(user.id);
                         let name = Username::new(user.name);
                         self.request_queue.users.push(rs2s::input::User(name.clone()));
                     }
+                    self.response_queue.users_followers.push((user_id, follower_ids));
                 },
                 Err(e) => {}
             }
@@ -192,18 +210,210 @@ impl State {
         Ok(())
     }
 
+    pub async fn request_studio(&mut self) -> Result<(), RequestError> {
+        type E = RequestError;
+        if let Some(request) = self.request_queue.studios.pop() {
+            let out = self.http_send(request).await.map_err(E::HttpSend)?;
+            if let Ok(out) = out {
+                let created = match chrono::DateTime::parse_from_rfc3339(&out.history.created) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        self.error_queue.push(Error::ParseDateTime(e));
+                        return Ok(());
+                    },
+                };
+                let modified = match chrono::DateTime::parse_from_rfc3339(&out.history.modified) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        self.error_queue.push(Error::ParseDateTime(e));
+                        return Ok(());
+                    },
+                };
+                self.response_queue.studios.push(db::Studio {
+                    id: out.id,
+                    open_to_all: out.open_to_all,
+                    comments_allowed: out.comments_allowed,
+                    host_id: out.host,
+                    created: created.timestamp(),
+                    modified: modified.timestamp(),
+                    title: out.title,
+                    description: out.description,
+                });
+
+                // Members and projects are written after the studio, see `respond_studio_lists`.
+                self.request_queue.studios_curators.push(rs2s::input::studio::Curators(out.id, ItemsRange { offset: 0, limit: 40 }));
+                self.request_queue.studios_managers.push((out.host, rs2s::input::studio::Managers(out.id, ItemsRange { offset: 0, limit: 40 })));
+                self.request_queue.studios_projects.push(rs2s::input::studio::Projects(out.id, ItemsRange { offset: 0, limit: 40 }));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn request_studio_members(&mut self) -> Result<(), RequestError> {
+        type E = RequestError;
+        if let Some((host_id, req)) = self.request_queue.studios_managers.pop() {
+            let out = self.http_send(req.clone()).await.map_err(E::HttpSend)?;
+            if let Ok(out) = out {
+                if out.len() == 40 {
+                    self.request_queue.studios_managers.push((host_id, rs2s::input::studio::Managers(req.0, ItemsRange { offset: req.1.offset + 40, limit: 40 })));
+                }
+                // The host is listed along with the managers.
+                let mut members = Vec::with_capacity(out.len());
+                for user in out {
+                    let role = if user.id == host_id { db::studio::Role::Host } else { db::studio::Role::Manager };
+                    members.push(db::studio::Member { user_id: user.id, role });
+                    self.request_queue.users.push(rs2s::input::User(Username::new(user.name)));
+                }
+                self.response_queue.studios_members.push((req.0, members));
+            }
+        }
+
+        if let Some(req) = self.request_queue.studios_curators.pop() {
+            let out = self.http_send(req.clone()).await.map_err(E::HttpSend)?;
+            if let Ok(out) = out {
+                if out.len() == 40 {
+                    self.request_queue.studios_curators.push(rs2s::input::studio::Curators(req.0, ItemsRange { offset: req.1.offset + 40, limit: 40 }));
+                }
+                let mut members = Vec::with_capacity(out.len());
+                for user in out {
+                    members.push(db::studio::Member { user_id: user.id, role: db::studio::Role::Curator });
+                    self.request_queue.users.push(rs2s::input::User(Username::new(user.name)));
+                }
+                self.response_queue.studios_members.push((req.0, members));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn request_studio_projects(&mut self) -> Result<(), RequestError> {
+        type E = RequestError;
+        if let Some(req) = self.request_queue.studios_projects.pop() {
+            let out = self.http_send(req.clone()).await.map_err(E::HttpSend)?;
+            if let Ok(out) = out {
+                if out.len() == 40 {
+                    self.request_queue.studios_projects.push(rs2s::input::studio::Projects(req.0, ItemsRange { offset: req.1.offset + 40, limit: 40 }));
+                }
+                let project_ids = out.into_iter().map(|project| project.id).collect();
+                self.response_queue.studios_projects.push((req.0, project_ids));
+            }
+        }
+        Ok(())
+    }
+
+    // Walks the pages of a forum category, queueing the posts of every topic.
+    pub async fn request_forum_topics(&mut self) -> Result<(), RequestError> {
+        type E = RequestError;
+        if let Some(req) = self.request_queue.forum_topics.pop() {
+            let out = self.http_send(req.clone()).await.map_err(E::HttpSend)?;
+            if let Ok(out) = out {
+                if out.len() == FORUM_TOPICS_PAGE_LEN {
+                    self.request_queue.forum_topics.push(rs2s::input::forum::Topics(req.0, req.1 + 1));
+                }
+                for topic in out {
+                    let Ok(author_name) = topic.author.parse() else {
+                        self.error_queue.push(Error::ParseUsername);
+                        continue;
+                    };
+                    let created = match chrono::DateTime::parse_from_rfc3339(&topic.created) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            self.error_queue.push(Error::ParseDateTime(e));
+                            continue;
+                        },
+                    };
+                    self.request_queue.forum_posts.push(rs2s::input::forum::Posts(topic.id, 1));
+                    self.response_queue.forum_topics.push(db::forum::topic::Value {
+                        id: topic.id,
+                        category_id: req.0,
+                        sticky: topic.sticky,
+                        closed: topic.closed,
+                        author_name,
+                        created: created.timestamp(),
+                        title: topic.title,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn request_forum_posts(&mut self) -> Result<(), RequestError> {
+        type E = RequestError;
+        if let Some(req) = self.request_queue.forum_posts.pop() {
+            let out = self.http_send(req.clone()).await.map_err(E::HttpSend)?;
+            if let Ok(out) = out {
+                if out.len() == FORUM_POSTS_PAGE_LEN {
+                    self.request_queue.forum_posts.push(rs2s::input::forum::Posts(req.0, req.1 + 1));
+                }
+                for post in out {
+                    let Ok(author_name) = post.author.parse() else {
+                        self.error_queue.push(Error::ParseUsername);
+                        continue;
+                    };
+                    let created = match chrono::DateTime::parse_from_rfc3339(&post.created) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            self.error_queue.push(Error::ParseDateTime(e));
+                            continue;
+                        },
+                    };
+                    let modified = match post.modified.as_deref().map(chrono::DateTime::parse_from_rfc3339) {
+                        Some(Ok(v)) => v.timestamp(),
+                        Some(Err(e)) => {
+                            self.error_queue.push(Error::ParseDateTime(e));
+                            continue;
+                        },
+                        None => 0,
+                    };
+                    self.response_queue.forum_posts.push(db::forum::post::Value {
+                        id: post.id,
+                        topic_id: req.0,
+                        author_name,
+                        created: created.timestamp(),
+                        modified,
+                        content: post.content,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub async fn request_all(&mut self) -> Result<(), RequestError> {
         self.request_user().await?;
         self.request_user_followers().await?;
+        self.request_studio().await?;
+        self.request_studio_members().await?;
+        self.request_studio_projects().await?;
+        self.request_forum_topics().await?;
+        self.request_forum_posts().await?;
         Ok(())
     }
 
+    async fn send_to_db<O: bincode::Decode>(&self, method: reqwest::Method, path: &str, body: impl bincode::Encode) -> Result<O, RespondError> {
+        let res = self.http_client
+            .request(method, self.db_url.join(path).unwrap())
+            .body(bincode::encode_to_vec(body, self.bincode_config).unwrap())
+            .header(
+                "x-auth-key",
+                self.auth_key.as_ref().map(|x| HeaderValue::from_bytes(x.as_bytes()).unwrap())
+                    .unwrap_or(HeaderValue::from_static(""))
+            )
+            .send()
+            .await
+            .map_err(RespondError::Http)?;
+        let bytes = res.bytes().await.map_err(RespondError::Http)?;
+        Ok(bincode::decode_from_slice::<O, _>(bytes.as_ref(), self.bincode_config)
+            .map_err(RespondError::Decode)?.0)
+    }
+
     pub async fn respond_user(&mut self) -> Result<(), RespondError> {
         if let Some(user) = self.response_queue.users.pop() {
             let name = Username::new(user.name.to_string());
+            let id = user.id;
 
             let res = self.http_client
-                .post(self.db_url.join("/users/write/bin/bin").unwrap())
+                .put(self.db_url.join("/users/write/bin/bin").unwrap())
                 .body(bincode::encode_to_vec(user, self.bincode_config).unwrap())
                 .header(
                     "x-auth-key",
@@ -219,7 +429,63 @@ impl State {
             println!("Db response: {:?}", &res);
 
             if res.is_ok_and(|i| !i) {
-                self.request_queue.users_followers.push(rs2s::input::user::Followers(name, rs2s::input::ItemsRange { offset: 0, limit: 40 }));
+                self.request_queue.users_followers.push((id, rs2s::input::user::Followers(name, rs2s::input::ItemsRange { offset: 0, limit: 40 })));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn respond_user_followers(&mut self) -> Result<(), RespondError> {
+        if let Some((id, follower_ids)) = self.response_queue.users_followers.pop() {
+            let res: db_http_server::users::WriteFollowersOutput = self.send_to_db(reqwest::Method::POST, &format!("/users/followers/{id}/bin/bin"), follower_ids).await?;
+            if let Err(e) = res {
+                self.error_queue.push(Error::WriteFollowers(e));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn respond_studio(&mut self) -> Result<(), RespondError> {
+        if let Some(studio) = self.response_queue.studios.pop() {
+            let res: db_http_server::studios::WriteOutput = self.send_to_db(reqwest::Method::PUT, "/studios/write/bin/bin", studio).await?;
+            if let Err(e) = res {
+                self.error_queue.push(Error::WriteStudio(e));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn respond_studio_lists(&mut self) -> Result<(), RespondError> {
+        // Members and projects can only be written once the studio is stored.
+        if !self.response_queue.studios.is_empty() {
+            return Ok(());
+        }
+        if let Some((id, members)) = self.response_queue.studios_members.pop() {
+            let res: db_http_server::studios::WriteListOutput = self.send_to_db(reqwest::Method::POST, &format!("/studios/members/{id}/bin/bin"), members).await?;
+            if let Err(e) = res {
+                self.error_queue.push(Error::WriteStudioList(e));
+            }
+        }
+        if let Some((id, project_ids)) = self.response_queue.studios_projects.pop() {
+            let res: db_http_server::studios::WriteListOutput = self.send_to_db(reqwest::Method::POST, &format!("/studios/projects/{id}/bin/bin"), project_ids).await?;
+            if let Err(e) = res {
+                self.error_queue.push(Error::WriteStudioList(e));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn respond_forum(&mut self) -> Result<(), RespondError> {
+        if let Some(topic) = self.response_queue.forum_topics.pop() {
+            let res: db_http_server::forum::TopicWriteOutput = self.send_to_db(reqwest::Method::PUT, "/forum/topics/write/bin/bin", topic).await?;
+            if let Err(e) = res {
+                self.error_queue.push(Error::WriteForumTopic(e));
+            }
+        }
+        if let Some(post) = self.response_queue.forum_posts.pop() {
+            let res: db_http_server::forum::PostWriteOutput = self.send_to_db(reqwest::Method::PUT, "/forum/posts/write/bin/bin", post).await?;
+            if let Err(e) = res {
+                self.error_queue.push(Error::WriteForumPost(e));
             }
         }
         Ok(())
@@ -227,6 +493,10 @@ impl State {
 
     pub async fn respond_all(&mut self) -> Result<(), RespondError> {
         self.respond_user().await?;
+        self.respond_user_followers().await?;
+        self.respond_studio().await?;
+        self.respond_studio_lists().await?;
+        self.respond_forum().await?;
         Ok(())
     }
 }
@@ -234,15 +504,32 @@ impl State {
 #[derive(Default)]
 pub struct ResponseQueue {
     pub users: Vec<db::User<'static>>,
+    // User id and a page of ids of its followers.
+    pub users_followers: Vec<(u64, Vec<u64>)>,
     pub projects: Vec<db::Project>,
+    pub studios: Vec<db::Studio>,
+    // Studio id and a page of its members or projects.
+    pub studios_members: Vec<(u64, Vec<db::studio::Member>)>,
+    pub studios_projects: Vec<(u64, Vec<u64>)>,
+    pub forum_topics: Vec<db::forum::topic::Value>,
+    pub forum_posts: Vec<db::forum::post::Value>,
 }
 
 #[derive(Default)]
 pub struct RequestQueue {
     pub users: Vec<rs2s::input::User<'static>>,
-    pub users_followers: Vec<rs2s::input::user::Followers<'static>>,
+    // Scratch id of the user and the request for its followers.
+    pub users_followers: Vec<(u64, rs2s::input::user::Followers<'static>)>,
     pub users_projects: Vec<rs2s::input::user::Projects<'static>>,
     pub projects: Vec<rs2s::input::Project>,
+    pub studios: Vec<rs2s::input::Studio>,
+    pub studios_curators: Vec<rs2s::input::studio::Curators>,
+    // Scratch id of the studio's host and the request for its managers.
+    pub studios_managers: Vec<(u64, rs2s::input::studio::Managers)>,
+    pub studios_projects: Vec<rs2s::input::studio::Projects>,
+    // Category or topic id and a page number (starting at 1).
+    pub forum_topics: Vec<rs2s::input::forum::Topics>,
+    pub forum_posts: Vec<rs2s::input::forum::Posts>,
 }
 
 // pub struct BoundQueue<T> {
@@ -0,0 +1,422 @@
+use std::collections::{HashMap, HashSet};
+use crate::{comment, entity::Entity, forum, project, search::SearchBackend, studio, user, BindbErrorKind, BindbErrorOp, InternalError};
+
+#[derive(Clone, Debug)]
+pub enum IssueKind {
+    // The index entry points at a stored entry that isn't live or has a different key.
+    StaleIndexEntry { id: u64 },
+    // A live stored entry is missing from the index.
+    MissingIndexEntry { id: u64 },
+    // The search document has no live stored entry.
+    StaleSearchDoc { id: u64 },
+    // A live stored entry has no search document.
+    MissingSearchDoc { id: u64 },
+    // A stored entry the primary index doesn't point at, it's freed when repairing.
+    LeakedEntry { id: u64 },
+    // An id is in the free list more than once, so it would be handed out twice.
+    DuplicateFreeId { id: u64 },
+    // The primary index points at an id in the free list, so a later add would overwrite it.
+    FreeIdInUse { id: u64 },
+    // The key refers to a studio or user that isn't stored, is out of sequence,
+    // or has a value that can't be decoded. It's removed when repairing.
+    StaleKey { key: String },
+    // A follow edge is in only one of `users_followers` and `users_following`, it's added to the other when repairing.
+    MissingKey { key: String },
+    // A live entry whose parent isn't stored, like a forum post of a removed topic.
+    // It's left behind by an interrupted removal of the parent, and removed when repairing.
+    Orphan { id: u64 },
+}
+
+#[derive(Clone, Debug)]
+pub struct Issue {
+    // Name of the storage, index or search index.
+    pub index: &'static str,
+    pub kind: IssueKind,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub issues: Vec<Issue>,
+    // Whether the issues were repaired.
+    pub repaired: bool,
+}
+
+// Checks every entry of an index against the stored entry it points at.
+// Entries that point at an id not in `$live` or don't match `$matches` are stale, and removed when repairing.
+// Live ids found less than `$per_id` times are returned, as they are missing from the index.
+macro_rules! check_index {
+    ($self: ident, $report: ident, $repair: expr, $live: expr, $per_id: expr, $index: ident, $storage: ident, $to_id: ident, |$key: ident, $data: ident| $matches: expr) => {{
+        let mut found = HashMap::<u64, usize>::new();
+        let mut stale = Vec::new();
+        for ($key, id) in $self.$index.iter() {
+            let id = id.$to_id();
+            // Live ids are stored, so only those are read.
+            if $live.contains(&id) && { let $data = $self.$storage.get(id); $matches } {
+                *found.entry(id).or_default() += 1;
+            } else {
+                $report.issues.push(Issue { index: stringify!($index), kind: IssueKind::StaleIndexEntry { id } });
+                stale.push($key);
+            }
+        }
+        if $repair {
+            for key in stale {
+                if let Ok(searched) = $self.$index.search(&key).find() {
+                    unsafe { $self.$index.remove_searched(&searched) }
+                        .map_err(|e| InternalError::bindb(BindbErrorOp::Check, BindbErrorKind::BinaryTreeRemove(e)))?;
+                }
+            }
+        }
+        let mut missing = Vec::new();
+        for id in $live.iter() {
+            if found.get(id).copied().unwrap_or(0) < $per_id {
+                $report.issues.push(Issue { index: stringify!($index), kind: IssueKind::MissingIndexEntry { id: *id } });
+                missing.push(*id);
+            }
+        }
+        missing
+    }};
+}
+
+// Ids of the stored entries the primary index points at, where the entry has the same key.
+// The other entries of the primary index are stale.
+macro_rules! live_ids {
+    ($self: ident, $report: ident, $repair: expr, $index: ident, $storage: ident, $to_id: ident, |$key: ident, $data: ident| $matches: expr) => {{
+        let live = $self.$index.iter()
+            .filter_map(|($key, id)| {
+                let id = id.$to_id();
+                if !$self.$storage.contains(id) {
+                    return None;
+                }
+                let $data = $self.$storage.get(id);
+                $matches.then_some(id)
+            })
+            .collect::<HashSet<_>>();
+        check_index!($self, $report, $repair, live, 1, $index, $storage, $to_id, |$key, $data| $matches);
+        live
+    }};
+}
+
+// Checks the free list of a storage against the ids the primary index points at,
+// and reports stored entries that aren't live. Those are freed when repairing.
+// The free list itself can't be rewritten, so its issues are only reported.
+macro_rules! check_storage {
+    ($self: ident, $report: ident, $repair: expr, $live: expr, $index: ident, $storage: ident, $to_id: ident) => {{
+        let mut free = HashSet::new();
+        for id in $self.$storage.free_ids() {
+            if !free.insert(id) {
+                $report.issues.push(Issue { index: stringify!($storage), kind: IssueKind::DuplicateFreeId { id } });
+            }
+        }
+        for (_, id) in $self.$index.iter() {
+            let id = id.$to_id();
+            if free.contains(&id) {
+                $report.issues.push(Issue { index: stringify!($storage), kind: IssueKind::FreeIdInUse { id } });
+            }
+        }
+        let leaked = $self.$storage.ids()
+            .filter(|id| !$live.contains(id) && !free.contains(id))
+            .collect::<Vec<_>>();
+        for id in leaked {
+            $report.issues.push(Issue { index: stringify!($storage), kind: IssueKind::LeakedEntry { id } });
+            if $repair {
+                unsafe { $self.$storage.remove(id) }
+                    .map_err(|e| InternalError::bindb(BindbErrorOp::Check, BindbErrorKind::IndexedDynamicRemove(e)))?;
+            }
+        }
+    }};
+}
+
+// Reports the keys of a tree for which `$valid` is false, and removes them when repairing.
+macro_rules! check_keys {
+    ($self: ident, $report: ident, $repair: expr, $tree: ident, |$key: ident, $value: ident| $valid: expr) => {{
+        let mut stale = Vec::new();
+        for ($key, $value) in $self.$tree.iter() {
+            if !$valid {
+                $report.issues.push(Issue { index: stringify!($tree), kind: IssueKind::StaleKey { key: format!("{:?}", $key) } });
+                stale.push($key);
+            }
+        }
+        if $repair {
+            for key in stale {
+                if let Ok(searched) = $self.$tree.search(&key).find() {
+                    unsafe { $self.$tree.remove_searched(&searched) }
+                        .map_err(|e| InternalError::bindb(BindbErrorOp::Check, BindbErrorKind::BinaryTreeRemove(e)))?;
+                }
+            }
+        }
+    }};
+}
+
+// Tracks the `idx` keys of per user sequences, which are read from 0 up to the first missing one.
+// Keys must come in key order, a key after a gap is out of sequence.
+#[derive(Default)]
+struct Sequence {
+    user_id: u64,
+    next: u64,
+}
+
+impl Sequence {
+    fn next_is(&mut self, user_id: u64, idx: u64) -> bool {
+        let expected = if self.user_id == user_id { self.next } else { 0 };
+        if idx != expected {
+            return false;
+        }
+        *self = Self { user_id, next: idx + 1 };
+        true
+    }
+}
+
+impl crate::Value {
+    // Reports the live entries `is_orphan` holds for. When repairing, they are removed and freed
+    // until none is left, as removing one can leave its own children without a parent.
+    async fn check_orphans<E: Entity>(
+        &mut self,
+        report: &mut Report,
+        repair: bool,
+        index: &'static str,
+        live: &mut HashSet<u64>,
+        is_orphan: fn(&Self, E) -> bool,
+    ) -> Result<(), InternalError> {
+        loop {
+            let orphans = live.iter().copied()
+                .filter(|id| is_orphan(self, E::storage(self).get(*id)))
+                .collect::<Vec<_>>();
+            for id in &orphans {
+                report.issues.push(Issue { index, kind: IssueKind::Orphan { id: *id } });
+                if repair {
+                    self.apply_remove::<E>(*id).await?;
+                    unsafe { E::storage_mut(self).remove(*id) }
+                        .map_err(|e| InternalError::bindb(BindbErrorOp::Check, BindbErrorKind::IndexedDynamicRemove(e)))?;
+                    live.remove(id);
+                }
+            }
+            if !repair || orphans.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    // Compares the search index `index` with the live entries.
+    // Returns the live entries without a document, stale documents are deleted when repairing.
+    async fn check_search(&self, report: &mut Report, repair: bool, index: &'static str, live: &HashSet<u64>) -> Result<Vec<u64>, InternalError> {
+        let ids = self.search.ids(index).await.map_err(InternalError::Search)?.into_iter().collect::<HashSet<_>>();
+        for id in ids.difference(live) {
+            report.issues.push(Issue { index, kind: IssueKind::StaleSearchDoc { id: *id } });
+            if repair {
+                self.search.delete(index, *id).await.map_err(InternalError::Search)?;
+            }
+        }
+        let missing = live.difference(&ids).copied().collect::<Vec<_>>();
+        for id in &missing {
+            report.issues.push(Issue { index, kind: IssueKind::MissingSearchDoc { id: *id } });
+        }
+        Ok(missing)
+    }
+
+    // Follow edges are written to both trees, an edge found in only one of them is added to the other when repairing.
+    unsafe fn check_follows(&mut self, report: &mut Report, repair: bool) -> Result<(), InternalError> {
+        let mirror = |key: &user::follow::Key| user::follow::Key { user_id: key.other_id, other_id: key.user_id };
+        let missing_following = self.users_followers.iter()
+            .filter(|(key, _)| self.users_following.get(&mirror(key)).is_none())
+            .map(|(key, time)| (mirror(&key), time))
+            .collect::<Vec<_>>();
+        let missing_followers = self.users_following.iter()
+            .filter(|(key, _)| self.users_followers.get(&mirror(key)).is_none())
+            .map(|(key, time)| (mirror(&key), time))
+            .collect::<Vec<_>>();
+        let e = |kind| InternalError::bindb(BindbErrorOp::Check, kind);
+        for (key, time) in missing_following {
+            report.issues.push(Issue { index: "users_following", kind: IssueKind::MissingKey { key: format!("{key:?}") } });
+            if repair {
+                Self::add_follow_edge(&mut self.users_following, &key, time).map_err(e)?;
+            }
+        }
+        for (key, time) in missing_followers {
+            report.issues.push(Issue { index: "users_followers", kind: IssueKind::MissingKey { key: format!("{key:?}") } });
+            if repair {
+                Self::add_follow_edge(&mut self.users_followers, &key, time).map_err(e)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Cross-validates the indexes against the stored entries, and the search indexes against the indexes.
+    // Studio lists, previous names and history are checked against the stored studios and users, forum posts against the topics, replies against the comments they reply to,
+    // and follows against each other.
+    // With `repair`, stale index entries, keys, search documents and orphans are removed, leaked entries are freed,
+    // and live entries are written again to every index they are missing from.
+    // Must be called after `recover`.
+    pub async fn check(&mut self, repair: bool) -> Result<Report, InternalError> {
+        let mut report = Report { issues: Vec::new(), repaired: repair };
+
+        let live = live_ids!(self, report, repair, users_id_index, users, unwrap, |key, data| data.fixed_data.id == key);
+        let mut missing = check_index!(self, report, repair, live, 1, users_name_index, users, unwrap,
+            |key, data| key.chars() == data.fixed_data.name.folded().chars());
+        missing.extend(check_index!(self, report, repair, live, user::top::Stat::ALL.len(), users_top_index, users, unwrap,
+            |key, data| user::top::Stat::ALL.iter().any(|stat| user::top::Key::new(*stat, &data.fixed_data) == key)));
+        missing.extend(self.check_search(&mut report, repair, "users", &live).await?);
+        if repair {
+            for id in missing.into_iter().collect::<HashSet<_>>() {
+                self.apply_add_user(id).await?;
+            }
+        }
+        check_storage!(self, report, repair, live, users_id_index, users, unwrap);
+        let mut sequence = Sequence::default();
+        check_keys!(self, report, repair, users_previous_names,
+            |key, _name| sequence.next_is(key.user_id, key.idx.into()) && self.users_id_index.get(&key.user_id).is_some());
+        let mut sequence = Sequence::default();
+        check_keys!(self, report, repair, users_history,
+            |key, _entry| sequence.next_is(key.user_id, key.idx.into()) && self.users_id_index.get(&key.user_id).is_some());
+        unsafe { self.check_follows(&mut report, repair) }?;
+
+        let live = live_ids!(self, report, repair, projects_id_index, projects, get, |key, data| data.id == key);
+        let mut missing = check_index!(self, report, repair, live, 1, projects_author_index, projects, get,
+            |key, data| project::AuthorKey::new(&data) == key);
+        missing.extend(self.check_search(&mut report, repair, "projects", &live).await?);
+        if repair {
+            for id in missing.into_iter().collect::<HashSet<_>>() {
+                self.apply_add::<project::DbRepr>(id).await?;
+            }
+        }
+        check_storage!(self, report, repair, live, projects_id_index, projects, get);
+
+        let live = live_ids!(self, report, repair, studios_id_index, studios, get, |key, data| data.id == key);
+        let missing = self.check_search(&mut report, repair, "studios", &live).await?;
+        if repair {
+            for id in missing {
+                self.apply_add::<studio::DbRepr>(id).await?;
+            }
+        }
+        check_storage!(self, report, repair, live, studios_id_index, studios, get);
+        let studio_ids = live.iter().map(|id| self.studios.get(*id).id).collect::<HashSet<_>>();
+        check_keys!(self, report, repair, studios_members,
+            |key, role| studio_ids.contains(&key.studio_id) && studio::Role::from_u8(role).is_some());
+        check_keys!(self, report, repair, studios_projects, |key, _added| studio_ids.contains(&key.studio_id));
+
+        let live = live_ids!(self, report, repair, forum_topics_id_index, forum_topics, get, |key, data| data.id == key);
+        let missing = check_index!(self, report, repair, live, 1, forum_topics_category_index, forum_topics, get,
+            |key, data| forum::topic::CategoryKey::new(&data) == key);
+        if repair {
+            for id in missing {
+                self.apply_add::<forum::topic::DbRepr>(id).await?;
+            }
+        }
+        check_storage!(self, report, repair, live, forum_topics_id_index, forum_topics, get);
+
+        let mut live = live_ids!(self, report, repair, forum_posts_id_index, forum_posts, get, |key, data| data.id == key);
+        self.check_orphans(&mut report, repair, "forum_posts", &mut live,
+            |db, data: forum::post::DbRepr| db.forum_topics_id_index.get(&data.topic_id).is_none()).await?;
+        let mut missing = check_index!(self, report, repair, live, 1, forum_posts_topic_index, forum_posts, get,
+            |key, data| forum::post::TopicKey::new(&data) == key);
+        missing.extend(self.check_search(&mut report, repair, "forum_posts", &live).await?);
+        if repair {
+            for id in missing.into_iter().collect::<HashSet<_>>() {
+                self.apply_add::<forum::post::DbRepr>(id).await?;
+            }
+        }
+        check_storage!(self, report, repair, live, forum_posts_id_index, forum_posts, get);
+
+        let mut live = live_ids!(self, report, repair, comments_id_index, comments, get,
+            |key, data| key.owner == data.owner && key.comment_id == data.id && comment::Owner::from_u8(data.owner).is_some());
+        self.check_orphans(&mut report, repair, "comments", &mut live, |db, data: comment::DbRepr| {
+            data.parent_id != 0 && db.comments_id_index.get(&comment::IdKey { owner: data.owner, comment_id: data.parent_id }).is_none()
+        }).await?;
+        let mut missing = check_index!(self, report, repair, live, 1, comments_thread_index, comments, get,
+            |key, data| comment::ThreadKey::new(&data) == key);
+        missing.extend(self.check_search(&mut report, repair, "comments", &live).await?);
+        if repair {
+            for id in missing.into_iter().collect::<HashSet<_>>() {
+                self.apply_add::<comment::DbRepr>(id).await?;
+            }
+        }
+        check_storage!(self, report, repair, live, comments_id_index, comments, get);
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IssueKind;
+    use crate::{comment, forum, studio, tests::{dir, open, user}, user::follow, Username};
+
+    fn comment(id: u64, parent_id: Option<u64>) -> comment::Value {
+        comment::Value {
+            owner: comment::Owner::Project,
+            owner_id: 1,
+            id,
+            parent_id,
+            author_name: "griffpatch".parse().unwrap(),
+            created: 0,
+            content: "comment".to_string(),
+        }
+    }
+
+    fn post(id: u64, topic_id: u64) -> forum::post::Value {
+        forum::post::Value {
+            id,
+            topic_id,
+            author_name: "griffpatch".parse().unwrap(),
+            created: 0,
+            modified: 0,
+            content: "post".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn repair_fixes_indexes_lists_and_leaks() {
+        let dir = dir("check_repair");
+        let mut db = open(&dir).await;
+        db.add_user(None, user("griffpatch", 1)).await.unwrap();
+        let id = db.users_id_index.get(&1).unwrap().unwrap();
+        // The name index lost its entry, a studio list outlived its studio,
+        // a follow edge was written to one tree only and an entry was stored without indexes.
+        let name = "griffpatch".parse::<Username>().unwrap().folded();
+        let searched = db.users_name_index.search(&name).find().unwrap();
+        unsafe { db.users_name_index.remove_searched(&searched) }.unwrap();
+        unsafe { db.studios_members.add(&studio::MemberKey { studio_id: 5, user_id: 1 }, &0) }.unwrap();
+        unsafe { db.users_followers.add(&follow::Key { user_id: 1, other_id: 2 }, &0) }.unwrap();
+        let leaked = unsafe { db.users.add(&user("kaj", 2).to_db_value()) }.unwrap();
+
+        let report = db.check(true).await.unwrap();
+        let has = |f: &dyn Fn(&super::Issue) -> bool| report.issues.iter().any(f);
+        assert!(has(&|issue| issue.index == "users_name_index" && matches!(issue.kind, IssueKind::MissingIndexEntry { id: missing } if missing == id)));
+        assert!(has(&|issue| issue.index == "users" && matches!(issue.kind, IssueKind::LeakedEntry { id } if id == leaked)));
+        assert!(has(&|issue| issue.index == "studios_members" && matches!(issue.kind, IssueKind::StaleKey { .. })));
+        assert!(has(&|issue| issue.index == "users_following" && matches!(issue.kind, IssueKind::MissingKey { .. })));
+
+        assert!(db.check(false).await.unwrap().issues.is_empty());
+        assert_eq!(db.user_by_name(None, &"GriffPatch".parse().unwrap()).unwrap().id, 1);
+        assert_eq!(db.user_following(None, 2, &follow::ListQuery::default()).unwrap(), vec![1]);
+        assert!(!db.users.contains(leaked));
+    }
+
+    #[tokio::test]
+    async fn repair_removes_posts_of_removed_topics() {
+        let dir = dir("check_orphan_posts");
+        let mut db = open(&dir).await;
+        db.add_forum_post(None, post(1, 5)).await.unwrap();
+
+        let report = db.check(true).await.unwrap();
+        assert!(report.issues.iter().any(|issue| issue.index == "forum_posts" && matches!(issue.kind, IssueKind::Orphan { .. })));
+        assert!(db.check(false).await.unwrap().issues.is_empty());
+        assert!(db.forum_post_by_id(None, &1).is_err());
+    }
+
+    #[tokio::test]
+    async fn repair_removes_replies_of_removed_comments() {
+        let dir = dir("check_orphan_comments");
+        let mut db = open(&dir).await;
+        db.add_comment(None, comment(1, None)).await.unwrap();
+        // A reply to a removed comment, and a reply to that reply.
+        db.add_comment(None, comment(3, Some(2))).await.unwrap();
+        db.add_comment(None, comment(4, Some(3))).await.unwrap();
+
+        let report = db.check(true).await.unwrap();
+        let orphans = report.issues.iter().filter(|issue| issue.index == "comments" && matches!(issue.kind, IssueKind::Orphan { .. }));
+        assert_eq!(orphans.count(), 2);
+        assert!(db.check(false).await.unwrap().issues.is_empty());
+        assert!(db.comment_by_id(None, comment::Owner::Project, 1).is_ok());
+        assert!(db.comment_by_id(None, comment::Owner::Project, 4).is_err());
+    }
+}
@@ -0,0 +1,428 @@
+use std::str::FromStr;
+use bindb::storage::IndexedDynamic;
+use crate::{auth, entity::{set_index, unset_index, Entity}, journal, list_limit, search::{self, FilterValue, SearchBackend}, BindbErrorKind, BindbErrorOp, Username};
+use binbuf::impls::dynamic::StringCLL;
+
+// What a comment was posted on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+#[serde(rename_all = "snake_case")]
+pub enum Owner {
+    Profile,
+    Project,
+    Studio,
+}
+
+impl Owner {
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Profile => 0,
+            Self::Project => 1,
+            Self::Studio => 2,
+        }
+    }
+
+    // `None` for bytes no owner kind is stored as.
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Profile),
+            1 => Some(Self::Project),
+            2 => Some(Self::Studio),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Profile => "profile",
+            Self::Project => "project",
+            Self::Studio => "studio",
+        }
+    }
+}
+
+impl FromStr for Owner {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "profile" => Ok(Self::Profile),
+            "project" => Ok(Self::Project),
+            "studio" => Ok(Self::Studio),
+            _ => Err(())
+        }
+    }
+}
+
+binbuf::dynamic! {
+    pub struct DbRepr {
+        pub owner: u8,
+        pub owner_id: u64,
+        pub id: u64,
+        // 0 for top level comments.
+        pub parent_id: u64,
+        pub author_name: Username,
+        pub created: i64,
+        pub content: StringCLL<2>,
+    }
+    buf! { pub struct DbReprBuf<P>(DbRepr, P); }
+    impl I for DbRepr { type Buf<P> = DbReprBuf<P>; }
+    impl Code for DbRepr {}
+}
+
+// The stored owner byte that isn't an `Owner`.
+#[derive(Clone, Copy, Debug)]
+pub struct UnknownOwner(pub u8);
+
+impl TryFrom<DbRepr> for Value {
+    type Error = UnknownOwner;
+    fn try_from(value: DbRepr) -> Result<Self, Self::Error> {
+        Ok(Self {
+            owner: Owner::from_u8(value.owner).ok_or(UnknownOwner(value.owner))?,
+            owner_id: value.owner_id,
+            id: value.id,
+            parent_id: (value.parent_id != 0).then_some(value.parent_id),
+            author_name: value.author_name,
+            created: value.created,
+            content: value.content.into(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct Value {
+    pub owner: Owner,
+    // Scratch id of the user (for profiles), project or studio.
+    pub owner_id: u64,
+    // Comment ids are only unique among comments of the same `owner` kind.
+    pub id: u64,
+    // Id of the comment this is a reply to.
+    pub parent_id: Option<u64>,
+    pub author_name: Username,
+    pub created: i64,
+    pub content: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ToDbReprError {
+    ContentTooLong,
+}
+
+impl Value {
+    pub fn to_db_repr(self) -> Result<DbRepr, ToDbReprError> {
+        Ok(DbRepr {
+            owner: self.owner.to_u8(),
+            owner_id: self.owner_id,
+            id: self.id,
+            parent_id: self.parent_id.unwrap_or(0),
+            author_name: self.author_name,
+            created: self.created,
+            content: StringCLL::try_from_string(self.content).ok_or(ToDbReprError::ContentTooLong)?,
+        })
+    }
+}
+
+// Key of a comment in the id index.
+binbuf::fixed! {
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct IdKey {
+        pub owner: u8,
+        pub comment_id: u64,
+    }
+
+    buf! { pub struct IdKeyBuf<P>(IdKey, P); }
+
+    impl I for IdKey {
+        type Buf<P> = IdKeyBuf<P>;
+    }
+    impl Code for IdKey {}
+}
+
+impl IdKey {
+    pub fn new(owner: Owner, comment_id: u64) -> Self {
+        Self { owner: owner.to_u8(), comment_id }
+    }
+}
+
+// Key of a comment in the thread index.
+// Comments of an owner are together, top level comments (`parent_id` 0) first.
+binbuf::fixed! {
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct ThreadKey {
+        pub owner: u8,
+        pub owner_id: u64,
+        pub parent_id: u64,
+        pub comment_id: u64,
+    }
+
+    buf! { pub struct ThreadKeyBuf<P>(ThreadKey, P); }
+
+    impl I for ThreadKey {
+        type Buf<P> = ThreadKeyBuf<P>;
+    }
+    impl Code for ThreadKey {}
+}
+
+impl ThreadKey {
+    pub fn new(db_repr: &DbRepr) -> Self {
+        Self {
+            owner: db_repr.owner,
+            owner_id: db_repr.owner_id,
+            parent_id: db_repr.parent_id,
+            comment_id: db_repr.id,
+        }
+    }
+}
+
+// A top level comment with all of its replies.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct Thread {
+    pub comment: Value,
+    // Replies to the comment and to its replies, sorted by id.
+    pub replies: Vec<Value>,
+}
+
+// A page of threads sorted by the id of the top level comment, starting right after the `after` id.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct ThreadsQuery {
+    pub after: Option<u64>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct MeiliDoc {
+    pub id: u64,
+    pub owner: String,
+    pub owner_id: u64,
+    pub author_name: String,
+    pub content: String,
+}
+
+impl MeiliDoc {
+    // An owner that can't be decoded is indexed as "", so no owner filter matches it.
+    pub fn new(id: u64, data: DbRepr) -> Self {
+        Self {
+            id,
+            owner: Owner::from_u8(data.owner).map_or("", Owner::as_str).to_string(),
+            owner_id: data.owner_id,
+            author_name: data.author_name.to_string(),
+            content: data.content.into(),
+        }
+    }
+
+    pub const FILTERABLE: [&'static str; 3] = ["owner", "owner_id", "author_name"];
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct SearchFilter {
+    pub owner: Option<Owner>,
+    pub owner_id: Option<u64>,
+    pub author_name: Option<Username>,
+}
+
+impl SearchFilter {
+    fn to_conditions(&self) -> Vec<search::Condition> {
+        let mut conditions = Vec::new();
+        if let Some(owner) = self.owner {
+            conditions.push(("owner", FilterValue::String(owner.as_str().to_string())));
+        }
+        if let Some(owner_id) = self.owner_id {
+            conditions.push(("owner_id", FilterValue::U64(owner_id)));
+        }
+        if let Some(author_name) = &self.author_name {
+            conditions.push(("author_name", FilterValue::String(author_name.to_string())));
+        }
+        conditions
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum GetByIdError {
+    Auth(auth::EnsureAuthError),
+    NotFound,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ThreadsError {
+    Auth(auth::EnsureAuthError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum AddError {
+    Internal,
+    Auth(auth::EnsureAuthError),
+    BadInput(ToDbReprError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum SearchError {
+    Internal,
+    Auth(auth::EnsureAuthError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum RemoveByIdError {
+    Internal,
+    Auth(auth::EnsureAuthError)
+}
+
+impl Entity for DbRepr {
+    const ADD_OP: BindbErrorOp = BindbErrorOp::AddComment;
+    const REPLACE_OP: BindbErrorOp = BindbErrorOp::UpsertComment;
+    const REMOVE_OP: BindbErrorOp = BindbErrorOp::RemoveCommentById;
+    const SEARCH_INDEX: Option<&'static str> = Some("comments");
+    type Doc = MeiliDoc;
+
+    fn storage(db: &crate::Value) -> &IndexedDynamic<Self> {
+        &db.comments
+    }
+
+    fn storage_mut(db: &mut crate::Value) -> &mut IndexedDynamic<Self> {
+        &mut db.comments
+    }
+
+    fn journal_entry(id: u64) -> journal::Entry {
+        journal::Entry::Comment(id)
+    }
+
+    fn add_intent(id: u64) -> journal::Intent {
+        journal::Intent::AddComment { id }
+    }
+
+    fn replace_intent(old_id: u64, id: u64) -> journal::Intent {
+        journal::Intent::ReplaceComment { old_id, id }
+    }
+
+    fn remove_intent(id: u64) -> journal::Intent {
+        journal::Intent::RemoveComment { id }
+    }
+
+    fn doc(id: u64, data: Self) -> MeiliDoc {
+        MeiliDoc::new(id, data)
+    }
+
+    unsafe fn set_indexes(db: &mut crate::Value, data: &Self, id: u64) -> Result<(), BindbErrorKind> {
+        set_index(&mut db.comments_id_index, &IdKey { owner: data.owner, comment_id: data.id }, id)?;
+        set_index(&mut db.comments_thread_index, &ThreadKey::new(data), id)
+    }
+
+    unsafe fn unset_indexes(db: &mut crate::Value, data: &Self, id: u64) -> Result<(), BindbErrorKind> {
+        unset_index(&mut db.comments_id_index, &IdKey { owner: data.owner, comment_id: data.id }, id)?;
+        unset_index(&mut db.comments_thread_index, &ThreadKey::new(data), id)
+    }
+}
+
+impl crate::Value {
+    // `(comment id, stored entry id)` of the comments replying to `parent_id` (0 for top level comments),
+    // sorted by the comment id and starting at `from`.
+    fn comment_children(&self, owner: u8, owner_id: u64, parent_id: u64, from: u64) -> impl Iterator<Item = (u64, u64)> + '_ {
+        iter_from!(self.comments_thread_index, ThreadKey { owner, owner_id, parent_id, comment_id: from })
+            .take_while(move |(key, _)| key.owner == owner && key.owner_id == owner_id && key.parent_id == parent_id)
+            .map(|(key, id)| (key.comment_id, id.get()))
+    }
+
+    // `(comment id, stored entry id)` of every reply to the comment and to its replies, sorted by the comment id.
+    fn comment_replies(&self, owner: u8, owner_id: u64, comment_id: u64) -> Vec<(u64, u64)> {
+        let mut replies = Vec::new();
+        let mut parents = vec![comment_id];
+        while let Some(parent_id) = parents.pop() {
+            for (reply_id, id) in self.comment_children(owner, owner_id, parent_id, 0) {
+                replies.push((reply_id, id));
+                parents.push(reply_id);
+            }
+        }
+        replies.sort();
+        replies
+    }
+
+    // Comments with an owner that can't be decoded are left out, `check` reports them.
+    pub fn comment_by_id(&self, auth_key: Option<&auth::Key>, owner: Owner, id: u64) -> Result<Value, GetByIdError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(GetByIdError::Auth)?;
+        self.comments_id_index.get(&IdKey::new(owner, id))
+            .and_then(|id| {
+                Value::try_from(self.comments.get(id.get())).ok()
+            })
+            .ok_or(GetByIdError::NotFound)
+    }
+
+    // Comment threads of a profile, project or studio.
+    // The id of the last top level comment in a page is the `after` cursor of the next page.
+    pub fn comment_threads(&self, auth_key: Option<&auth::Key>, owner: Owner, owner_id: u64, query: &ThreadsQuery) -> Result<Vec<Thread>, ThreadsError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(ThreadsError::Auth)?;
+        let Some(from) = query.after.map_or(Some(0), |after| after.checked_add(1)) else {
+            return Ok(Vec::new());
+        };
+        let owner = owner.to_u8();
+        Ok(self.comment_children(owner, owner_id, 0, from)
+            .filter_map(|(comment_id, id)| Some(Thread {
+                comment: self.comments.get(id).try_into().ok()?,
+                replies: self.comment_replies(owner, owner_id, comment_id).into_iter()
+                    .filter_map(|(_, id)| self.comments.get(id).try_into().ok())
+                    .collect(),
+            }))
+            .take(list_limit(query.limit))
+            .collect())
+    }
+
+    // Returns true if already exists.
+    pub async fn add_comment(&mut self, auth_key: Option<&auth::Key>, value: Value) -> Result<bool, AddError> {
+        self.ensure_auth(auth::Op::Write, auth_key).map_err(AddError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| AddError::Internal)?;
+        if self.comments_id_index.get(&IdKey::new(value.owner, value.id)).is_some() {
+            return Ok(true);
+        }
+
+        let db_repr = value.to_db_repr().map_err(AddError::BadInput)?;
+        self.write_add(&db_repr).await.map_err(|_| AddError::Internal)?;
+        Ok(false)
+    }
+
+    // Inserts the comment, or overwrites the existing comment with the same owner kind and id.
+    // Returns true if already exists.
+    pub async fn upsert_comment(&mut self, auth_key: Option<&auth::Key>, value: Value) -> Result<bool, AddError> {
+        self.ensure_auth(auth::Op::Write, auth_key).map_err(AddError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| AddError::Internal)?;
+        let old_id = match self.comments_id_index.get(&IdKey::new(value.owner, value.id)) {
+            Some(old_id) => old_id.get(),
+            None => return self.add_comment(auth_key, value).await,
+        };
+
+        let db_repr = value.to_db_repr().map_err(AddError::BadInput)?;
+        self.write_replace(old_id, &db_repr).await.map_err(|_| AddError::Internal)?;
+        Ok(true)
+    }
+
+    pub async fn search_comments(&self, auth_key: Option<&auth::Key>, query: &str, filter: &SearchFilter) -> Result<Vec<Value>, SearchError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(SearchError::Auth)?;
+        let ids = match self.search.search("comments", query, &filter.to_conditions()).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                self.send_search_error(e).await;
+                Err(SearchError::Internal)?
+            }
+        };
+
+        Ok(ids.into_iter()
+            .filter_map(|id| Value::try_from(self.comments.get(id)).ok())
+            .collect())
+    }
+
+    // Also removes the replies to the comment.
+    // Returns true if didn't exist.
+    pub async fn remove_comment_by_id(&mut self, auth_key: Option<&auth::Key>, owner: Owner, id: u64) -> Result<bool, RemoveByIdError> {
+        self.ensure_auth(auth::Op::Remove, auth_key).map_err(RemoveByIdError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| RemoveByIdError::Internal)?;
+        let comment_id = id;
+        let Some(id) = self.comments_id_index.get(&IdKey::new(owner, comment_id)) else {
+            return Ok(true);
+        };
+        let id = id.get();
+
+        let owner_id = self.comments.get(id).owner_id;
+        // Replies are newer than what they reply to, so removing them newest first doesn't leave a reply without its parent
+        // if the removal is interrupted. `check` reports replies left without one.
+        for (_, reply_id) in self.comment_replies(owner.to_u8(), owner_id, comment_id).into_iter().rev() {
+            self.write_remove::<DbRepr>(reply_id).await.map_err(|_| RemoveByIdError::Internal)?;
+        }
+        self.write_remove::<DbRepr>(id).await.map_err(|_| RemoveByIdError::Internal)?;
+        Ok(false)
+    }
+}
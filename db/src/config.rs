@@ -1,9 +1,27 @@
 use bindb::storage::OpenMode;
 use serde::{Deserialize, Serialize};
 
+// Fixed when the database is created, kept in its manifest.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Create {
+    
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Root {
     pub require_auth: RequireAuth,
+    pub search: Search,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Search {
+    Meili {
+        host: String,
+        key: String,
+    },
+    // Runs in the database process and is stored in the database folder, doesn't require meilisearch.
+    Embedded,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
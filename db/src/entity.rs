@@ -0,0 +1,215 @@
+use binbuf::impls::ArbNum;
+use bindb::storage::{BinaryTree, IndexedDynamic};
+use serde::Serialize;
+use crate::{journal, search::SearchBackend, BindbErrorKind, BindbErrorOp, InternalError};
+
+// Maps keys derived from stored entries to the ids of the entries.
+pub type Index<K> = BinaryTree<ArbNum<8, u64>, K, ArbNum<8, u64>>;
+
+// Points `key` at `id`, replacing the entry if the key is already taken.
+pub(crate) unsafe fn set_index<K>(index: &mut Index<K>, key: &K, id: u64) -> Result<(), BindbErrorKind>
+where K: binbuf::Fixed + binbuf::fixed::Decode + Ord {
+    match index.search(key).find() {
+        Ok(searched) => {
+            index.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+            index.add(key, &ArbNum::new(id)).map_err(BindbErrorKind::BinaryTreeAdd)?;
+        },
+        Err(searched) => {
+            index.add_searched(&searched, key, &ArbNum::new(id)).map_err(BindbErrorKind::BinaryTreeAdd)?;
+        }
+    }
+    Ok(())
+}
+
+// Removes `key` only if it still points at `id`.
+pub(crate) unsafe fn unset_index<K>(index: &mut Index<K>, key: &K, id: u64) -> Result<(), BindbErrorKind>
+where K: binbuf::Fixed + binbuf::fixed::Decode + Ord {
+    if let Ok(searched) = index.search(key).find() {
+        if index.get_searched(&searched).get() == id {
+            index.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+        }
+    }
+    Ok(())
+}
+
+// A stored representation of projects, studios, forum topics and posts, and comments.
+// Implementations derive the index keys and the search document from an entry,
+// storing, replacing and removing entries through the journal is shared.
+pub(crate) trait Entity: binbuf::Dynamic + binbuf::dynamic::Decode + Sized {
+    const ADD_OP: BindbErrorOp;
+    const REPLACE_OP: BindbErrorOp;
+    const REMOVE_OP: BindbErrorOp;
+    // `None` if the entity isn't searchable.
+    const SEARCH_INDEX: Option<&'static str>;
+    type Doc: Serialize + Send + Sync;
+
+    fn storage(db: &crate::Value) -> &IndexedDynamic<Self>;
+    fn storage_mut(db: &mut crate::Value) -> &mut IndexedDynamic<Self>;
+    fn journal_entry(id: u64) -> journal::Entry;
+    fn add_intent(id: u64) -> journal::Intent;
+    fn replace_intent(old_id: u64, id: u64) -> journal::Intent;
+    fn remove_intent(id: u64) -> journal::Intent;
+    fn doc(id: u64, data: Self) -> Self::Doc;
+
+    // Points every index at the stored entry `id` with the keys of `data`.
+    unsafe fn set_indexes(db: &mut crate::Value, data: &Self, id: u64) -> Result<(), BindbErrorKind>;
+    // Removes the keys of `data` that still point at `id` from every index.
+    unsafe fn unset_indexes(db: &mut crate::Value, data: &Self, id: u64) -> Result<(), BindbErrorKind>;
+
+    // Removes what's stored along with the entry, like the members of a studio. Must be idempotent.
+    unsafe fn remove_owned(_db: &mut crate::Value, _data: &Self) -> Result<(), BindbErrorKind> {
+        Ok(())
+    }
+}
+
+impl crate::Value {
+    async fn add_doc<E: Entity>(&self, id: u64) -> Result<(), InternalError> {
+        let Some(index) = E::SEARCH_INDEX else {
+            return Ok(());
+        };
+        let doc = E::doc(id, E::storage(self).get(id));
+        self.search.index(index, &[doc]).await.map_err(InternalError::Search)
+    }
+
+    async fn delete_doc<E: Entity>(&self, id: u64) -> Result<(), InternalError> {
+        let Some(index) = E::SEARCH_INDEX else {
+            return Ok(());
+        };
+        self.search.delete(index, id).await.map_err(InternalError::Search)
+    }
+
+    // Makes the indexes and search point at the stored entry `id`.
+    // Every step is idempotent, so it's also used to finish an interrupted add.
+    pub(crate) async fn apply_add<E: Entity>(&mut self, id: u64) -> Result<(), InternalError> {
+        let data = E::storage(self).get(id);
+        unsafe { E::set_indexes(self, &data, id) }
+            .map_err(|kind| InternalError::bindb(E::ADD_OP, kind))?;
+        self.add_doc::<E>(id).await
+    }
+
+    async fn rollback_add<E: Entity>(&mut self, id: u64) -> Result<(), InternalError> {
+        let data = E::storage(self).get(id);
+        unsafe { E::unset_indexes(self, &data, id) }
+            .map_err(|kind| InternalError::bindb(E::ADD_OP, kind))?;
+        self.delete_doc::<E>(id).await?;
+        self.finish_intent(E::ADD_OP, Some(E::journal_entry(id))).await;
+        Ok(())
+    }
+
+    // Moves the indexes and search from the stored entry `old_id` to `id`.
+    // Every step is idempotent, so it's also used to finish an interrupted replace.
+    pub(crate) async fn apply_replace<E: Entity>(&mut self, old_id: u64, id: u64) -> Result<(), InternalError> {
+        let old = E::storage(self).get(old_id);
+        let data = E::storage(self).get(id);
+        let e = |kind| InternalError::bindb(E::REPLACE_OP, kind);
+        unsafe {
+            E::unset_indexes(self, &old, old_id).map_err(e)?;
+            E::set_indexes(self, &data, id).map_err(e)?;
+        }
+        self.add_doc::<E>(id).await?;
+        self.delete_doc::<E>(old_id).await
+    }
+
+    async fn rollback_replace<E: Entity>(&mut self, old_id: u64, id: u64) -> Result<(), InternalError> {
+        let old = E::storage(self).get(old_id);
+        let data = E::storage(self).get(id);
+        let e = |kind| InternalError::bindb(E::REPLACE_OP, kind);
+        unsafe {
+            E::unset_indexes(self, &data, id).map_err(e)?;
+            E::set_indexes(self, &old, old_id).map_err(e)?;
+        }
+        self.add_doc::<E>(old_id).await?;
+        self.delete_doc::<E>(id).await?;
+        self.finish_intent(E::REPLACE_OP, Some(E::journal_entry(id))).await;
+        Ok(())
+    }
+
+    // Removes the stored entry `id` from the indexes and search, along with what it owns.
+    // Every step is idempotent, so it's also used to finish an interrupted remove.
+    pub(crate) async fn apply_remove<E: Entity>(&mut self, id: u64) -> Result<(), InternalError> {
+        let data = E::storage(self).get(id);
+        let e = |kind| InternalError::bindb(E::REMOVE_OP, kind);
+        self.delete_doc::<E>(id).await?;
+        unsafe {
+            E::unset_indexes(self, &data, id).map_err(e)?;
+            E::remove_owned(self, &data).map_err(e)?;
+        }
+        Ok(())
+    }
+
+    // What the entry owned and was already removed isn't restored.
+    async fn rollback_remove<E: Entity>(&mut self, id: u64) -> Result<(), InternalError> {
+        self.apply_add::<E>(id).await?;
+        self.finish_intent(E::REMOVE_OP, None).await;
+        Ok(())
+    }
+
+    // Stores `data` as a new entry and records the intent to write it.
+    // On failure nothing is left behind.
+    async fn add_entry<E: Entity>(&mut self, op: BindbErrorOp, data: &E, intent: impl FnOnce(u64) -> journal::Intent) -> Result<u64, ()> {
+        let id = match unsafe { E::storage_mut(self).add(data) } {
+            Ok(id) => id,
+            Err(e) => {
+                self.send_bindb_error(op, BindbErrorKind::IndexedDynamicAdd(e)).await;
+                Err(())?
+            }
+        };
+        if let Err(e) = self.journal.write(&intent(id)) {
+            self.send_error(InternalError::Journal(e)).await;
+            if let Err(e) = unsafe { E::storage_mut(self).remove(id) } {
+                self.send_bindb_error(op, BindbErrorKind::IndexedDynamicRemove(e)).await;
+            }
+            Err(())?
+        }
+        Ok(id)
+    }
+
+    // Stores `data` as a new entry and makes the indexes and search point at it.
+    // Errors are sent to the error channel, the write is then rolled back or left to recovery.
+    pub(crate) async fn write_add<E: Entity>(&mut self, data: &E) -> Result<(), ()> {
+        let id = self.add_entry(E::ADD_OP, data, |id| E::add_intent(id)).await?;
+        if let Err(e) = self.apply_add::<E>(id).await {
+            self.send_error(e).await;
+            if let Err(e) = self.rollback_add::<E>(id).await {
+                self.send_error(e).await;
+                self.needs_recovery = true;
+            }
+            Err(())?
+        }
+        self.finish_intent(E::ADD_OP, None).await;
+        Ok(())
+    }
+
+    // Overwrites the stored entry `old_id` with `data`, see `write_add`.
+    pub(crate) async fn write_replace<E: Entity>(&mut self, old_id: u64, data: &E) -> Result<(), ()> {
+        let id = self.add_entry(E::REPLACE_OP, data, |id| E::replace_intent(old_id, id)).await?;
+        if let Err(e) = self.apply_replace::<E>(old_id, id).await {
+            self.send_error(e).await;
+            if let Err(e) = self.rollback_replace::<E>(old_id, id).await {
+                self.send_error(e).await;
+                self.needs_recovery = true;
+            }
+            Err(())?
+        }
+        self.finish_intent(E::REPLACE_OP, Some(E::journal_entry(old_id))).await;
+        Ok(())
+    }
+
+    // Removes the stored entry `id`, see `write_add`.
+    pub(crate) async fn write_remove<E: Entity>(&mut self, id: u64) -> Result<(), ()> {
+        if let Err(e) = self.journal.write(&E::remove_intent(id)) {
+            self.send_error(InternalError::Journal(e)).await;
+            Err(())?
+        }
+        if let Err(e) = self.apply_remove::<E>(id).await {
+            self.send_error(e).await;
+            if let Err(e) = self.rollback_remove::<E>(id).await {
+                self.send_error(e).await;
+                self.needs_recovery = true;
+            }
+            Err(())?
+        }
+        self.finish_intent(E::REMOVE_OP, Some(E::journal_entry(id))).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,9 @@
+pub mod topic;
+pub mod post;
+
+// A page of topics or posts sorted by id, starting right after the `after` id.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct ListQuery {
+    pub after: Option<u64>,
+    pub limit: Option<u32>,
+}
@@ -0,0 +1,283 @@
+use bindb::storage::IndexedDynamic;
+use crate::{auth, entity::{set_index, unset_index, Entity}, journal, list_limit, search::{self, FilterValue, SearchBackend}, BindbErrorKind, BindbErrorOp, Username};
+use binbuf::impls::dynamic::StringCLL;
+use super::ListQuery;
+
+binbuf::dynamic! {
+    pub struct DbRepr {
+        pub id: u64,
+        pub topic_id: u64,
+        pub author_name: Username,
+        pub created: i64,
+        // 0 if the post was never edited.
+        pub modified: i64,
+        // BBCode.
+        pub content: StringCLL<2>,
+    }
+    buf! { pub struct DbReprBuf<P>(DbRepr, P); }
+    impl I for DbRepr { type Buf<P> = DbReprBuf<P>; }
+    impl Code for DbRepr {}
+}
+
+impl From<DbRepr> for Value {
+    fn from(value: DbRepr) -> Self {
+        Self {
+            id: value.id,
+            topic_id: value.topic_id,
+            author_name: value.author_name,
+            created: value.created,
+            modified: value.modified,
+            content: value.content.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct Value {
+    pub id: u64,
+    pub topic_id: u64,
+    pub author_name: Username,
+    pub created: i64,
+    pub modified: i64,
+    pub content: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ToDbReprError {
+    ContentTooLong,
+}
+
+impl Value {
+    pub fn to_db_repr(self) -> Result<DbRepr, ToDbReprError> {
+        Ok(DbRepr {
+            id: self.id,
+            topic_id: self.topic_id,
+            author_name: self.author_name,
+            created: self.created,
+            modified: self.modified,
+            content: StringCLL::try_from_string(self.content).ok_or(ToDbReprError::ContentTooLong)?,
+        })
+    }
+}
+
+// Key of a post in the topic index.
+binbuf::fixed! {
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct TopicKey {
+        pub topic_id: u64,
+        pub post_id: u64,
+    }
+
+    buf! { pub struct TopicKeyBuf<P>(TopicKey, P); }
+
+    impl I for TopicKey {
+        type Buf<P> = TopicKeyBuf<P>;
+    }
+    impl Code for TopicKey {}
+}
+
+impl TopicKey {
+    pub fn new(db_repr: &DbRepr) -> Self {
+        Self {
+            topic_id: db_repr.topic_id,
+            post_id: db_repr.id,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct MeiliDoc {
+    pub id: u64,
+    pub topic_id: u64,
+    pub author_name: String,
+    pub content: String,
+}
+
+impl MeiliDoc {
+    pub fn new(id: u64, value: Value) -> Self {
+        Self {
+            id,
+            topic_id: value.topic_id,
+            author_name: value.author_name.to_string(),
+            content: value.content,
+        }
+    }
+
+    pub const FILTERABLE: [&'static str; 2] = ["topic_id", "author_name"];
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct SearchFilter {
+    pub topic_id: Option<u64>,
+    pub author_name: Option<Username>,
+}
+
+impl SearchFilter {
+    fn to_conditions(&self) -> Vec<search::Condition> {
+        let mut conditions = Vec::new();
+        if let Some(topic_id) = self.topic_id {
+            conditions.push(("topic_id", FilterValue::U64(topic_id)));
+        }
+        if let Some(author_name) = &self.author_name {
+            conditions.push(("author_name", FilterValue::String(author_name.to_string())));
+        }
+        conditions
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum GetByIdError {
+    Auth(auth::EnsureAuthError),
+    NotFound,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ByTopicError {
+    Auth(auth::EnsureAuthError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum AddError {
+    Internal,
+    Auth(auth::EnsureAuthError),
+    BadInput(ToDbReprError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum SearchError {
+    Internal,
+    Auth(auth::EnsureAuthError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum RemoveByIdError {
+    Internal,
+    Auth(auth::EnsureAuthError)
+}
+
+impl Entity for DbRepr {
+    const ADD_OP: BindbErrorOp = BindbErrorOp::AddForumPost;
+    const REPLACE_OP: BindbErrorOp = BindbErrorOp::UpsertForumPost;
+    const REMOVE_OP: BindbErrorOp = BindbErrorOp::RemoveForumPostById;
+    const SEARCH_INDEX: Option<&'static str> = Some("forum_posts");
+    type Doc = MeiliDoc;
+
+    fn storage(db: &crate::Value) -> &IndexedDynamic<Self> {
+        &db.forum_posts
+    }
+
+    fn storage_mut(db: &mut crate::Value) -> &mut IndexedDynamic<Self> {
+        &mut db.forum_posts
+    }
+
+    fn journal_entry(id: u64) -> journal::Entry {
+        journal::Entry::ForumPost(id)
+    }
+
+    fn add_intent(id: u64) -> journal::Intent {
+        journal::Intent::AddForumPost { id }
+    }
+
+    fn replace_intent(old_id: u64, id: u64) -> journal::Intent {
+        journal::Intent::ReplaceForumPost { old_id, id }
+    }
+
+    fn remove_intent(id: u64) -> journal::Intent {
+        journal::Intent::RemoveForumPost { id }
+    }
+
+    fn doc(id: u64, data: Self) -> MeiliDoc {
+        MeiliDoc::new(id, data.into())
+    }
+
+    unsafe fn set_indexes(db: &mut crate::Value, data: &Self, id: u64) -> Result<(), BindbErrorKind> {
+        set_index(&mut db.forum_posts_id_index, &data.id, id)?;
+        set_index(&mut db.forum_posts_topic_index, &TopicKey::new(data), id)
+    }
+
+    unsafe fn unset_indexes(db: &mut crate::Value, data: &Self, id: u64) -> Result<(), BindbErrorKind> {
+        unset_index(&mut db.forum_posts_id_index, &data.id, id)?;
+        unset_index(&mut db.forum_posts_topic_index, &TopicKey::new(data), id)
+    }
+}
+
+impl crate::Value {
+    pub fn forum_post_by_id(&self, auth_key: Option<&auth::Key>, id: &u64) -> Result<Value, GetByIdError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(GetByIdError::Auth)?;
+        self.forum_posts_id_index.get(id)
+            .map(|id| {
+                self.forum_posts.get(id.get()).into()
+            })
+            .ok_or(GetByIdError::NotFound)
+    }
+
+    // Posts of the topic, sorted by id (which is the order they were posted in).
+    // The id of the last post in a page is the `after` cursor of the next page.
+    pub fn forum_posts_by_topic(&self, auth_key: Option<&auth::Key>, topic_id: u64, query: &ListQuery) -> Result<Vec<Value>, ByTopicError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(ByTopicError::Auth)?;
+        let Some(post_id) = query.after.map_or(Some(0), |after| after.checked_add(1)) else {
+            return Ok(Vec::new());
+        };
+        Ok(iter_from!(self.forum_posts_topic_index, TopicKey { topic_id, post_id })
+            .take_while(|(key, _)| key.topic_id == topic_id)
+            .take(list_limit(query.limit))
+            .map(|(_, id)| self.forum_posts.get(id.get()).into())
+            .collect())
+    }
+
+    // Returns true if already exists.
+    pub async fn add_forum_post(&mut self, auth_key: Option<&auth::Key>, value: Value) -> Result<bool, AddError> {
+        self.ensure_auth(auth::Op::Write, auth_key).map_err(AddError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| AddError::Internal)?;
+        if self.forum_posts_id_index.get(&value.id).is_some() {
+            return Ok(true);
+        }
+
+        let db_repr = value.to_db_repr().map_err(AddError::BadInput)?;
+        self.write_add(&db_repr).await.map_err(|_| AddError::Internal)?;
+        Ok(false)
+    }
+
+    // Inserts the post, or overwrites the existing post with the same id.
+    // Returns true if already exists.
+    pub async fn upsert_forum_post(&mut self, auth_key: Option<&auth::Key>, value: Value) -> Result<bool, AddError> {
+        self.ensure_auth(auth::Op::Write, auth_key).map_err(AddError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| AddError::Internal)?;
+        let old_id = match self.forum_posts_id_index.get(&value.id) {
+            Some(old_id) => old_id.get(),
+            None => return self.add_forum_post(auth_key, value).await,
+        };
+
+        let db_repr = value.to_db_repr().map_err(AddError::BadInput)?;
+        self.write_replace(old_id, &db_repr).await.map_err(|_| AddError::Internal)?;
+        Ok(true)
+    }
+
+    pub async fn search_forum_posts(&self, auth_key: Option<&auth::Key>, query: &str, filter: &SearchFilter) -> Result<Vec<Value>, SearchError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(SearchError::Auth)?;
+        let ids = match self.search.search("forum_posts", query, &filter.to_conditions()).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                self.send_search_error(e).await;
+                Err(SearchError::Internal)?
+            }
+        };
+
+        let mut hits = Vec::with_capacity(ids.len());
+        for id in ids {
+            hits.push(Value::from(self.forum_posts.get(id)));
+        }
+        Ok(hits)
+    }
+
+    // Returns true if didn't exist.
+    pub async fn remove_forum_post_by_id(&mut self, auth_key: Option<&auth::Key>, id: &u64) -> Result<bool, RemoveByIdError> {
+        self.ensure_auth(auth::Op::Remove, auth_key).map_err(RemoveByIdError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| RemoveByIdError::Internal)?;
+        let Some(id) = self.forum_posts_id_index.get(id) else {
+            return Ok(true);
+        };
+        self.write_remove::<DbRepr>(id.get()).await.map_err(|_| RemoveByIdError::Internal)?;
+        Ok(false)
+    }
+}
@@ -0,0 +1,243 @@
+use bindb::storage::IndexedDynamic;
+use crate::{auth, entity::{set_index, unset_index, Entity}, journal, list_limit, BindbErrorKind, BindbErrorOp, Username};
+use binbuf::impls::dynamic::StringCLL;
+use super::ListQuery;
+
+bitflags::bitflags! {
+    pub struct Flags: u8 {
+        const STICKY = 1;
+        const CLOSED = 1 << 1;
+    }
+}
+
+binbuf::dynamic! {
+    pub struct DbRepr {
+        pub flags: u8,
+        pub id: u64,
+        pub category_id: u64,
+        pub author_name: Username,
+        pub created: i64,
+        pub title: StringCLL<2>,
+    }
+    buf! { pub struct DbReprBuf<P>(DbRepr, P); }
+    impl I for DbRepr { type Buf<P> = DbReprBuf<P>; }
+    impl Code for DbRepr {}
+}
+
+impl From<DbRepr> for Value {
+    fn from(value: DbRepr) -> Self {
+        let flags = Flags::from_bits_retain(value.flags);
+        Self {
+            id: value.id,
+            category_id: value.category_id,
+            sticky: flags.contains(Flags::STICKY),
+            closed: flags.contains(Flags::CLOSED),
+            author_name: value.author_name,
+            created: value.created,
+            title: value.title.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct Value {
+    pub id: u64,
+    pub category_id: u64,
+    pub sticky: bool,
+    pub closed: bool,
+    pub author_name: Username,
+    pub created: i64,
+    pub title: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ToDbReprError {
+    TitleTooLong,
+}
+
+impl Value {
+    pub fn to_db_repr(self) -> Result<DbRepr, ToDbReprError> {
+        let mut flags = Flags::empty();
+        if self.sticky { flags |= Flags::STICKY; }
+        if self.closed { flags |= Flags::CLOSED; }
+        Ok(DbRepr {
+            flags: flags.bits(),
+            id: self.id,
+            category_id: self.category_id,
+            author_name: self.author_name,
+            created: self.created,
+            title: StringCLL::try_from_string(self.title).ok_or(ToDbReprError::TitleTooLong)?,
+        })
+    }
+}
+
+// Key of a topic in the category index.
+binbuf::fixed! {
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct CategoryKey {
+        pub category_id: u64,
+        pub topic_id: u64,
+    }
+
+    buf! { pub struct CategoryKeyBuf<P>(CategoryKey, P); }
+
+    impl I for CategoryKey {
+        type Buf<P> = CategoryKeyBuf<P>;
+    }
+    impl Code for CategoryKey {}
+}
+
+impl CategoryKey {
+    pub fn new(db_repr: &DbRepr) -> Self {
+        Self {
+            category_id: db_repr.category_id,
+            topic_id: db_repr.id,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum GetByIdError {
+    Auth(auth::EnsureAuthError),
+    NotFound,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ByCategoryError {
+    Auth(auth::EnsureAuthError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum AddError {
+    Internal,
+    Auth(auth::EnsureAuthError),
+    BadInput(ToDbReprError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum RemoveByIdError {
+    Internal,
+    Auth(auth::EnsureAuthError)
+}
+
+impl Entity for DbRepr {
+    const ADD_OP: BindbErrorOp = BindbErrorOp::AddForumTopic;
+    const REPLACE_OP: BindbErrorOp = BindbErrorOp::UpsertForumTopic;
+    const REMOVE_OP: BindbErrorOp = BindbErrorOp::RemoveForumTopicById;
+    const SEARCH_INDEX: Option<&'static str> = None;
+    type Doc = ();
+
+    fn storage(db: &crate::Value) -> &IndexedDynamic<Self> {
+        &db.forum_topics
+    }
+
+    fn storage_mut(db: &mut crate::Value) -> &mut IndexedDynamic<Self> {
+        &mut db.forum_topics
+    }
+
+    fn journal_entry(id: u64) -> journal::Entry {
+        journal::Entry::ForumTopic(id)
+    }
+
+    fn add_intent(id: u64) -> journal::Intent {
+        journal::Intent::AddForumTopic { id }
+    }
+
+    fn replace_intent(old_id: u64, id: u64) -> journal::Intent {
+        journal::Intent::ReplaceForumTopic { old_id, id }
+    }
+
+    fn remove_intent(id: u64) -> journal::Intent {
+        journal::Intent::RemoveForumTopic { id }
+    }
+
+    fn doc(_id: u64, _data: Self) {}
+
+    unsafe fn set_indexes(db: &mut crate::Value, data: &Self, id: u64) -> Result<(), BindbErrorKind> {
+        set_index(&mut db.forum_topics_id_index, &data.id, id)?;
+        set_index(&mut db.forum_topics_category_index, &CategoryKey::new(data), id)
+    }
+
+    unsafe fn unset_indexes(db: &mut crate::Value, data: &Self, id: u64) -> Result<(), BindbErrorKind> {
+        unset_index(&mut db.forum_topics_id_index, &data.id, id)?;
+        unset_index(&mut db.forum_topics_category_index, &CategoryKey::new(data), id)
+    }
+}
+
+impl crate::Value {
+    pub fn forum_topic_by_id(&self, auth_key: Option<&auth::Key>, id: &u64) -> Result<Value, GetByIdError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(GetByIdError::Auth)?;
+        self.forum_topics_id_index.get(id)
+            .map(|id| {
+                self.forum_topics.get(id.get()).into()
+            })
+            .ok_or(GetByIdError::NotFound)
+    }
+
+    // Topics of the category, sorted by id.
+    // The id of the last topic in a page is the `after` cursor of the next page.
+    pub fn forum_topics_by_category(&self, auth_key: Option<&auth::Key>, category_id: u64, query: &ListQuery) -> Result<Vec<Value>, ByCategoryError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(ByCategoryError::Auth)?;
+        let Some(topic_id) = query.after.map_or(Some(0), |after| after.checked_add(1)) else {
+            return Ok(Vec::new());
+        };
+        Ok(iter_from!(self.forum_topics_category_index, CategoryKey { category_id, topic_id })
+            .take_while(|(key, _)| key.category_id == category_id)
+            .take(list_limit(query.limit))
+            .map(|(_, id)| self.forum_topics.get(id.get()).into())
+            .collect())
+    }
+
+    // Returns true if already exists.
+    pub async fn add_forum_topic(&mut self, auth_key: Option<&auth::Key>, value: Value) -> Result<bool, AddError> {
+        self.ensure_auth(auth::Op::Write, auth_key).map_err(AddError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| AddError::Internal)?;
+        if self.forum_topics_id_index.get(&value.id).is_some() {
+            return Ok(true);
+        }
+
+        let db_repr = value.to_db_repr().map_err(AddError::BadInput)?;
+        self.write_add(&db_repr).await.map_err(|_| AddError::Internal)?;
+        Ok(false)
+    }
+
+    // Inserts the topic, or overwrites the existing topic with the same id.
+    // Returns true if already exists.
+    pub async fn upsert_forum_topic(&mut self, auth_key: Option<&auth::Key>, value: Value) -> Result<bool, AddError> {
+        self.ensure_auth(auth::Op::Write, auth_key).map_err(AddError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| AddError::Internal)?;
+        let old_id = match self.forum_topics_id_index.get(&value.id) {
+            Some(old_id) => old_id.get(),
+            None => return self.add_forum_topic(auth_key, value).await,
+        };
+
+        let db_repr = value.to_db_repr().map_err(AddError::BadInput)?;
+        self.write_replace(old_id, &db_repr).await.map_err(|_| AddError::Internal)?;
+        Ok(true)
+    }
+
+    // Also removes the topic's posts.
+    // Returns true if didn't exist.
+    pub async fn remove_forum_topic_by_id(&mut self, auth_key: Option<&auth::Key>, id: &u64) -> Result<bool, RemoveByIdError> {
+        self.ensure_auth(auth::Op::Remove, auth_key).map_err(RemoveByIdError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| RemoveByIdError::Internal)?;
+        let topic_id = *id;
+        let Some(id) = self.forum_topics_id_index.get(&topic_id) else {
+            return Ok(true);
+        };
+        let id = id.get();
+
+        let post_ids = iter_from!(self.forum_posts_topic_index, super::post::TopicKey { topic_id, post_id: 0 })
+            .map(|(key, _)| key)
+            .take_while(|key| key.topic_id == topic_id)
+            .map(|key| key.post_id)
+            .collect::<Vec<_>>();
+        for post_id in post_ids {
+            self.remove_forum_post_by_id(auth_key, &post_id).await
+                .map_err(|_| RemoveByIdError::Internal)?;
+        }
+
+        self.write_remove::<DbRepr>(id).await.map_err(|_| RemoveByIdError::Internal)?;
+        Ok(false)
+    }
+}
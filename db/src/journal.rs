@@ -0,0 +1,308 @@
+use std::{fs::{self, File}, io::{self, Write}, path::PathBuf};
+use crate::{comment, entity::Entity, forum, project, studio, user, BindbErrorKind, BindbErrorOp, InternalError};
+
+// A logical write in progress, referring to entries in `users`, `projects`, `studios`, `forum_topics`, `forum_posts` or `comments`.
+// It's recorded once the new entry (if any) is stored, and cleared after the unused entry (if any) is freed.
+// Changes to studio lists and follows don't refer to entries and are always applied again.
+// So an intent found in the journal either refers to live entries and can be finished by applying it again,
+// or refers to a freed entry, in which case it was finished and only clearing the journal is left.
+#[derive(Clone, Debug, bincode::Encode, bincode::Decode)]
+pub enum Intent {
+    AddUser { id: u64 },
+    ReplaceUser { old_id: u64, id: u64 },
+    RemoveUser { id: u64 },
+    AddProject { id: u64 },
+    ReplaceProject { old_id: u64, id: u64 },
+    RemoveProject { id: u64 },
+    AddStudio { id: u64 },
+    ReplaceStudio { old_id: u64, id: u64 },
+    RemoveStudio { id: u64 },
+    AddForumTopic { id: u64 },
+    ReplaceForumTopic { old_id: u64, id: u64 },
+    RemoveForumTopic { id: u64 },
+    AddForumPost { id: u64 },
+    ReplaceForumPost { old_id: u64, id: u64 },
+    RemoveForumPost { id: u64 },
+    AddComment { id: u64 },
+    ReplaceComment { old_id: u64, id: u64 },
+    RemoveComment { id: u64 },
+    WriteStudioList(studio::ListWrite),
+    WriteFollows(user::follow::Write),
+}
+
+impl Intent {
+    // Every entry the intent refers to.
+    pub fn entries(&self) -> Vec<Entry> {
+        match *self {
+            Self::AddUser { id } | Self::RemoveUser { id } => vec![Entry::User(id)],
+            Self::ReplaceUser { old_id, id } => vec![Entry::User(old_id), Entry::User(id)],
+            Self::AddProject { id } | Self::RemoveProject { id } => vec![Entry::Project(id)],
+            Self::ReplaceProject { old_id, id } => vec![Entry::Project(old_id), Entry::Project(id)],
+            Self::AddStudio { id } | Self::RemoveStudio { id } => vec![Entry::Studio(id)],
+            Self::ReplaceStudio { old_id, id } => vec![Entry::Studio(old_id), Entry::Studio(id)],
+            Self::AddForumTopic { id } | Self::RemoveForumTopic { id } => vec![Entry::ForumTopic(id)],
+            Self::ReplaceForumTopic { old_id, id } => vec![Entry::ForumTopic(old_id), Entry::ForumTopic(id)],
+            Self::AddForumPost { id } | Self::RemoveForumPost { id } => vec![Entry::ForumPost(id)],
+            Self::ReplaceForumPost { old_id, id } => vec![Entry::ForumPost(old_id), Entry::ForumPost(id)],
+            Self::AddComment { id } | Self::RemoveComment { id } => vec![Entry::Comment(id)],
+            Self::ReplaceComment { old_id, id } => vec![Entry::Comment(old_id), Entry::Comment(id)],
+            Self::WriteStudioList(_) | Self::WriteFollows(_) => vec![],
+        }
+    }
+}
+
+// An entry freed once an intent is finished.
+#[derive(Clone, Copy, Debug)]
+pub enum Entry {
+    User(u64),
+    Project(u64),
+    Studio(u64),
+    ForumTopic(u64),
+    ForumPost(u64),
+    Comment(u64),
+}
+
+pub struct Value {
+    path: PathBuf,
+}
+
+impl Value {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn read(&self) -> io::Result<Option<Intent>> {
+        match fs::read(&self.path) {
+            Ok(bytes) if bytes.is_empty() => Ok(None),
+            Ok(bytes) => bincode::decode_from_slice(&bytes, bincode::config::standard())
+                .map(|x| Some(x.0))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn write(&self, intent: &Intent) -> io::Result<()> {
+        let bytes = bincode::encode_to_vec(intent, bincode::config::standard())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        self.write_bytes(&bytes)
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        self.write_bytes(&[])
+    }
+
+    // Writes to a temporary file first, so the journal is never torn.
+    fn write_bytes(&self, bytes: &[u8]) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+impl crate::Value {
+    // Frees the entry that's no longer referenced, then clears the intent.
+    // If the journal can't be cleared, the intent is found on recovery with the entry already freed, and only cleared then.
+    pub(crate) async fn finish_intent(&mut self, op: BindbErrorOp, free: Option<Entry>) {
+        if let Some(entry) = free {
+            if let Err(e) = self.free_entry(entry) {
+                self.send_bindb_error(op, BindbErrorKind::IndexedDynamicRemove(e)).await;
+            }
+        }
+        if let Err(e) = self.journal.clear() {
+            self.send_error(InternalError::Journal(e)).await;
+            self.needs_recovery = true;
+        }
+    }
+
+    fn is_stored(&self, entry: Entry) -> bool {
+        match entry {
+            Entry::User(id) => self.users.contains(id),
+            Entry::Project(id) => self.projects.contains(id),
+            Entry::Studio(id) => self.studios.contains(id),
+            Entry::ForumTopic(id) => self.forum_topics.contains(id),
+            Entry::ForumPost(id) => self.forum_posts.contains(id),
+            Entry::Comment(id) => self.comments.contains(id),
+        }
+    }
+
+    // Does nothing if the entry was already freed, so it can be repeated on recovery.
+    fn free_entry(&mut self, entry: Entry) -> Result<(), bindb::storage::indexed_dynamic::RemoveError> {
+        if !self.is_stored(entry) {
+            return Ok(());
+        }
+        match entry {
+            Entry::User(id) => unsafe { self.users.remove(id) },
+            Entry::Project(id) => unsafe { self.projects.remove(id) },
+            Entry::Studio(id) => unsafe { self.studios.remove(id) },
+            Entry::ForumTopic(id) => unsafe { self.forum_topics.remove(id) },
+            Entry::ForumPost(id) => unsafe { self.forum_posts.remove(id) },
+            Entry::Comment(id) => unsafe { self.comments.remove(id) },
+        }
+    }
+
+    // Frees the entry that's no longer referenced after a recovered intent, then clears the journal.
+    fn finish_recovered(&mut self, op: BindbErrorOp, free: Option<Entry>) -> Result<(), InternalError> {
+        if let Some(entry) = free {
+            self.free_entry(entry)
+                .map_err(|e| InternalError::bindb(op, BindbErrorKind::IndexedDynamicRemove(e)))?;
+        }
+        self.journal.clear().map_err(InternalError::Journal)
+    }
+
+    async fn recover_add<E: Entity>(&mut self, id: u64) -> Result<(), InternalError> {
+        self.apply_add::<E>(id).await?;
+        self.finish_recovered(E::ADD_OP, None)
+    }
+
+    async fn recover_replace<E: Entity>(&mut self, old_id: u64, id: u64) -> Result<(), InternalError> {
+        self.apply_replace::<E>(old_id, id).await?;
+        self.finish_recovered(E::REPLACE_OP, Some(E::journal_entry(old_id)))
+    }
+
+    async fn recover_remove<E: Entity>(&mut self, id: u64) -> Result<(), InternalError> {
+        self.apply_remove::<E>(id).await?;
+        self.finish_recovered(E::REMOVE_OP, Some(E::journal_entry(id)))
+    }
+
+    // Recovers before a write if an earlier one was left unfinished.
+    // The write is refused only if recovering fails again.
+    pub(crate) async fn ensure_recovered(&mut self) -> Result<(), ()> {
+        if !self.needs_recovery {
+            return Ok(());
+        }
+        if let Err(e) = self.recover().await {
+            self.send_error(e).await;
+            Err(())?
+        }
+        Ok(())
+    }
+
+    // Finishes a write that was interrupted by a crash or a failed rollback.
+    // Called by `open`, and again before the next write while it keeps failing.
+    pub async fn recover(&mut self) -> Result<(), InternalError> {
+        let intent = self.journal.read().map_err(InternalError::Journal)?;
+        if let Some(intent) = &intent {
+            if intent.entries().into_iter().any(|entry| !self.is_stored(entry)) {
+                self.journal.clear().map_err(InternalError::Journal)?;
+                self.needs_recovery = false;
+                return Ok(());
+            }
+        }
+        match intent {
+            Some(Intent::AddUser { id }) => {
+                self.apply_add_user(id).await?;
+                self.finish_recovered(BindbErrorOp::AddUser, None)?;
+            },
+            Some(Intent::ReplaceUser { old_id, id }) => {
+                self.apply_replace_user(old_id, id).await?;
+                self.finish_recovered(BindbErrorOp::ReplaceUser, Some(Entry::User(old_id)))?;
+            },
+            Some(Intent::RemoveUser { id }) => {
+                self.apply_remove_user(id).await?;
+                self.finish_recovered(BindbErrorOp::RemoveUserByName, Some(Entry::User(id)))?;
+            },
+            Some(Intent::AddProject { id }) => self.recover_add::<project::DbRepr>(id).await?,
+            Some(Intent::ReplaceProject { old_id, id }) => self.recover_replace::<project::DbRepr>(old_id, id).await?,
+            Some(Intent::RemoveProject { id }) => self.recover_remove::<project::DbRepr>(id).await?,
+            Some(Intent::AddStudio { id }) => self.recover_add::<studio::DbRepr>(id).await?,
+            Some(Intent::ReplaceStudio { old_id, id }) => self.recover_replace::<studio::DbRepr>(old_id, id).await?,
+            Some(Intent::RemoveStudio { id }) => self.recover_remove::<studio::DbRepr>(id).await?,
+            Some(Intent::AddForumTopic { id }) => self.recover_add::<forum::topic::DbRepr>(id).await?,
+            Some(Intent::ReplaceForumTopic { old_id, id }) => self.recover_replace::<forum::topic::DbRepr>(old_id, id).await?,
+            Some(Intent::RemoveForumTopic { id }) => self.recover_remove::<forum::topic::DbRepr>(id).await?,
+            Some(Intent::AddForumPost { id }) => self.recover_add::<forum::post::DbRepr>(id).await?,
+            Some(Intent::ReplaceForumPost { old_id, id }) => self.recover_replace::<forum::post::DbRepr>(old_id, id).await?,
+            Some(Intent::RemoveForumPost { id }) => self.recover_remove::<forum::post::DbRepr>(id).await?,
+            Some(Intent::AddComment { id }) => self.recover_add::<comment::DbRepr>(id).await?,
+            Some(Intent::ReplaceComment { old_id, id }) => self.recover_replace::<comment::DbRepr>(old_id, id).await?,
+            Some(Intent::RemoveComment { id }) => self.recover_remove::<comment::DbRepr>(id).await?,
+            Some(Intent::WriteStudioList(write)) => {
+                unsafe { self.apply_list_write(&write) }
+                    .map_err(|kind| InternalError::bindb(write.op(), kind))?;
+                self.finish_recovered(write.op(), None)?;
+            },
+            Some(Intent::WriteFollows(write)) => {
+                unsafe { self.apply_follow_write(&write) }
+                    .map_err(|kind| InternalError::bindb(BindbErrorOp::WriteFollows, kind))?;
+                self.finish_recovered(BindbErrorOp::WriteFollows, None)?;
+            },
+            None => {}
+        }
+        self.needs_recovery = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Intent;
+    use crate::{tests::{dir, open, user}, user};
+
+    #[tokio::test]
+    async fn open_finishes_interrupted_add() {
+        let dir = dir("journal_add");
+        let mut db = open(&dir).await;
+        // Crashed after the entry and the intent were written, before any index.
+        let id = unsafe { db.users.add(&user("griffpatch", 1).to_db_value()) }.unwrap();
+        db.journal.write(&Intent::AddUser { id }).unwrap();
+        drop(db);
+
+        let db = open(&dir).await;
+        assert!(db.journal.read().unwrap().is_none());
+        assert_eq!(db.user_by_name(None, &"griffpatch".parse().unwrap()).unwrap().id, 1);
+        assert_eq!(db.user_by_id(None, &1).unwrap().name, "griffpatch".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn open_clears_intent_with_freed_entry() {
+        let dir = dir("journal_freed");
+        let mut db = open(&dir).await;
+        db.add_user(None, user("griffpatch", 1)).await.unwrap();
+        let old_id = db.users_id_index.get(&1).unwrap().unwrap();
+        // Crashed after a rolled back replace freed its new entry, before the journal was cleared.
+        let id = unsafe { db.users.add(&user("griffpatch", 1).to_db_value()) }.unwrap();
+        db.journal.write(&Intent::ReplaceUser { old_id, id }).unwrap();
+        unsafe { db.users.remove(id) }.unwrap();
+        drop(db);
+
+        let db = open(&dir).await;
+        assert!(db.journal.read().unwrap().is_none());
+        assert_eq!(db.users_id_index.get(&1).unwrap().unwrap(), old_id);
+    }
+
+    #[tokio::test]
+    async fn open_finishes_interrupted_follows() {
+        let dir = dir("journal_follows");
+        let mut db = open(&dir).await;
+        // Crashed after the first edge was written to one of the two indexes.
+        let write = user::follow::Write::AddFollowers { user_id: 1, follower_ids: vec![2, 3], added: 0 };
+        db.journal.write(&Intent::WriteFollows(write)).unwrap();
+        unsafe { db.users_followers.add(&user::follow::Key { user_id: 1, other_id: 2 }, &0) }.unwrap();
+        drop(db);
+
+        let db = open(&dir).await;
+        assert!(db.journal.read().unwrap().is_none());
+        let query = user::follow::ListQuery::default();
+        assert_eq!(db.user_followers(None, 1, &query).unwrap(), vec![2, 3]);
+        assert_eq!(db.user_following(None, 2, &query).unwrap(), vec![1]);
+        assert_eq!(db.user_following(None, 3, &query).unwrap(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn write_recovers_first() {
+        let dir = dir("journal_write");
+        let mut db = open(&dir).await;
+        let id = unsafe { db.users.add(&user("griffpatch", 1).to_db_value()) }.unwrap();
+        db.journal.write(&Intent::AddUser { id }).unwrap();
+        db.needs_recovery = true;
+
+        assert!(!db.add_user(None, user("kaj", 2)).await.unwrap());
+        assert!(!db.needs_recovery);
+        assert!(db.journal.read().unwrap().is_none());
+        assert!(db.user_by_name(None, &"griffpatch".parse().unwrap()).is_ok());
+        assert!(db.user_by_name(None, &"kaj".parse().unwrap()).is_ok());
+    }
+}
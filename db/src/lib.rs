@@ -1,25 +1,62 @@
 use std::{borrow::Cow, fs::File, path::{Path, PathBuf}, str::FromStr};
-use meilisearch_sdk::client::Client as MeiliClient;
 use binbuf::{BytesPtr, bytes_ptr, impls::{ArbNum, arb_num}};
 pub use bindb::storage::OpenMode;
 use ring::rand::SecureRandom;
 pub use username::Value as Username;
 pub use user::Value as User;
 pub use project::Value as Project;
+pub use studio::Value as Studio;
+pub use comment::Value as Comment;
 use tokio::sync::mpsc;
 // pub use country::Value as Country;
 
+// Iterates a `BinaryTree` in key order, starting at the first key not less than `$from`.
+// The search only positions the walk, so it takes O(log n) to reach the start of a page.
+macro_rules! iter_from {
+    ($tree: expr, $from: expr) => {{
+        let from = $from;
+        let searched = match $tree.search(&from).find() {
+            Ok(searched) | Err(searched) => searched,
+        };
+        $tree.iter_from(&searched).skip_while(move |(key, _)| *key < from)
+    }};
+}
+
 pub mod auth;
 pub mod username;
 // pub mod country;
 pub mod project;
+pub mod studio;
+pub mod forum;
+pub mod comment;
 pub mod user;
 pub mod config;
+pub mod journal;
+pub mod search;
+pub mod snapshot;
+pub mod check;
+pub mod migrate;
+pub mod manifest;
+mod entity;
+
+// Version of the layout of the stored data, recorded in the manifest of the database folder.
+// Databases of older versions have to be migrated with `migrate::migrate`, backups of other versions can't be restored.
+// Bump it together with a new step in `migrate::STEPS` whenever the layout changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+pub const LIST_DEFAULT_LIMIT: u32 = 100;
+pub const LIST_MAX_LIMIT: u32 = 1000;
+
+pub(crate) fn list_limit(limit: Option<u32>) -> usize {
+    limit.unwrap_or(LIST_DEFAULT_LIMIT).min(LIST_MAX_LIMIT) as usize
+}
 
 #[derive(Debug)]
 pub enum InternalError {
-    Meili(meilisearch_sdk::errors::Error),
+    Search(search::Error),
     Bindb(BindbError),
+    Journal(std::io::Error),
+    Snapshot(std::io::Error),
 }
 
 impl InternalError {
@@ -44,11 +81,31 @@ impl BindbError {
 pub enum BindbErrorOp {
     GenAuth,
     AddUser,
+    ReplaceUser,
     UserByName,
     SearchUsers,
     RemoveUserByName,
     AddProject,
+    UpsertProject,
     ProjectById,
+    RemoveProjectById,
+    AddStudio,
+    UpsertStudio,
+    RemoveStudioById,
+    WriteStudioMembers,
+    WriteStudioProjects,
+    WriteFollows,
+    Check,
+    AddForumTopic,
+    UpsertForumTopic,
+    RemoveForumTopicById,
+    AddForumPost,
+    UpsertForumPost,
+    RemoveForumPostById,
+    AddComment,
+    UpsertComment,
+    RemoveCommentById,
+    Migrate,
 }
 
 #[derive(Debug)]
@@ -67,7 +124,19 @@ pub enum OpenError {
     OpenDynamic(bindb::storage::dynamic::OpenError),
     OpenIndexedDynamic(bindb::storage::indexed_dynamic::OpenError),
     OpenBinaryTree(bindb::storage::binary_tree::OpenError),
-    OpenSingle(bindb::storage::single::OpenError)
+    OpenSingle(bindb::storage::single::OpenError),
+    Search(search::Error),
+    // The database was written with another `FORMAT_VERSION`, older ones can be migrated.
+    FormatVersion { found: u32 },
+    // The folder wasn't set up with `manifest::create`, or is from before the manifest was added and has to be migrated.
+    NoManifest,
+    InvalidManifest(serde_json::Error),
+    // Another process has the database open.
+    Locked,
+    // The write left in the journal couldn't be finished.
+    Recover(InternalError),
+    // A file of a storage is missing. Storages added by newer versions are created by `migrate::migrate`.
+    MissingStorage(&'static str),
 }
 
 impl From<bindb::storage::fixed::OpenError> for OpenError {
@@ -103,25 +172,116 @@ impl From<bindb::storage::single::OpenError> for OpenError {
 
 pub struct Value {
     config: config::Root,
+    dir: PathBuf,
+    // Names of the storage files in `dir`, in the order they were opened.
+    files: Vec<&'static str>,
+    // Holds the lock on `manifest::LOCK_FILE`, released when dropped.
+    lock: File,
     auth: auth::Store,
     pub users: bindb::storage::IndexedDynamic<user::DbValue>,
+    // Keyed by case folded names, Scratch usernames are case insensitive.
     users_name_index: bindb::storage::BinaryTree<ArbNum<4, u64>, Username, ArbNum<4, u64>>,
+    users_id_index: bindb::storage::BinaryTree<ArbNum<4, u64>, u64, ArbNum<4, u64>>,
+    users_previous_names: bindb::storage::BinaryTree<ArbNum<4, u64>, user::PreviousNameKey, Username>,
+    users_history: user::history::Store,
+    users_top_index: user::top::Index,
+    users_followers: user::follow::Index,
+    users_following: user::follow::Index,
     projects: bindb::storage::IndexedDynamic<project::DbRepr>,
     projects_id_index: bindb::storage::BinaryTree<ArbNum<8, u64>, u64, ArbNum<8, u64>>,
-    meili_client: MeiliClient,
+    projects_author_index: bindb::storage::BinaryTree<ArbNum<8, u64>, project::AuthorKey, ArbNum<8, u64>>,
+    studios: bindb::storage::IndexedDynamic<studio::DbRepr>,
+    studios_id_index: bindb::storage::BinaryTree<ArbNum<8, u64>, u64, ArbNum<8, u64>>,
+    studios_members: studio::Members,
+    studios_projects: studio::Projects,
+    forum_topics: bindb::storage::IndexedDynamic<forum::topic::DbRepr>,
+    forum_topics_id_index: bindb::storage::BinaryTree<ArbNum<8, u64>, u64, ArbNum<8, u64>>,
+    forum_topics_category_index: bindb::storage::BinaryTree<ArbNum<8, u64>, forum::topic::CategoryKey, ArbNum<8, u64>>,
+    forum_posts: bindb::storage::IndexedDynamic<forum::post::DbRepr>,
+    forum_posts_id_index: bindb::storage::BinaryTree<ArbNum<8, u64>, u64, ArbNum<8, u64>>,
+    forum_posts_topic_index: bindb::storage::BinaryTree<ArbNum<8, u64>, forum::post::TopicKey, ArbNum<8, u64>>,
+    comments: bindb::storage::IndexedDynamic<comment::DbRepr>,
+    comments_id_index: bindb::storage::BinaryTree<ArbNum<8, u64>, comment::IdKey, ArbNum<8, u64>>,
+    comments_thread_index: bindb::storage::BinaryTree<ArbNum<8, u64>, comment::ThreadKey, ArbNum<8, u64>>,
+    search: search::Backend,
+    journal: journal::Value,
+    // Set while the journal holds an unfinished write, which is recovered before the next one.
+    needs_recovery: bool,
     error_sender: mpsc::Sender<InternalError>
 }
 
 impl Value {
-    pub unsafe fn open(
-        meili_client: MeiliClient,
+    // Opens a database folder set up with `manifest::create`, its storages are created on the first open.
+    // Fails with `OpenError::Locked` while another process has it open.
+    // A write left unfinished by a crash is recovered before returning.
+    pub async unsafe fn open(
         dir_path: impl AsRef<Path>,
+        config: config::Root,
+        error_sender: mpsc::Sender<InternalError>,
+    ) -> Result<Self, OpenError> {
+        let dir_path = dir_path.as_ref();
+        let lock = manifest::lock(dir_path)?;
+        let mut manifest = match manifest::read(dir_path) {
+            Ok(Some(manifest)) => manifest,
+            Ok(None) => Err(OpenError::NoManifest)?,
+            Err(manifest::Error::Io(e)) => Err(OpenError::Io(e))?,
+            Err(manifest::Error::Invalid(e)) => Err(OpenError::InvalidManifest(e))?,
+        };
+        if manifest.format_version != FORMAT_VERSION {
+            Err(OpenError::FormatVersion { found: manifest.format_version })?
+        }
+
+        let mode = if manifest.storages.is_empty() { OpenMode::New } else { OpenMode::Existing };
+        let mut value = Self::open_storages(dir_path, mode, &[], lock, config, error_sender)?;
+        if let OpenMode::New = mode {
+            manifest.storages = value.files.iter().map(|name| name.to_string()).collect();
+            manifest::write(dir_path, &manifest).map_err(OpenError::Io)?;
+        }
+        value.recover().await.map_err(OpenError::Recover)?;
+        Ok(value)
+    }
+
+    // Writes the mapped pages of every storage to its files, so copies of the files see every write.
+    pub fn flush(&self) -> std::io::Result<()> {
+        macro_rules! flush {
+            ($($storage: ident),* $(,)?) => {
+                $(self.$storage.flush()?;)*
+            };
+        }
+        flush!(
+            auth,
+            users, users_name_index, users_id_index, users_previous_names, users_history, users_top_index, users_followers, users_following,
+            projects, projects_id_index, projects_author_index,
+            studios, studios_id_index, studios_members, studios_projects,
+            forum_topics, forum_topics_id_index, forum_topics_category_index,
+            forum_posts, forum_posts_id_index, forum_posts_topic_index,
+            comments, comments_id_index, comments_thread_index,
+        );
+        Ok(())
+    }
+
+    // Opens every storage without checking the manifest, `lock` must be held on the folder.
+    // Storages in `create` whose files don't exist yet are created empty, which migrations rely on.
+    // Any other missing file fails with `OpenError::MissingStorage`.
+    unsafe fn open_storages(
+        dir_path: &Path,
         mode: OpenMode,
+        create: &[&str],
+        lock: File,
         config: config::Root,
         error_sender: mpsc::Sender<InternalError>,
     ) -> Result<Self, OpenError> {
         use bindb::storage;
-        let dir_path = dir_path.as_ref();
+        let storage_mode = |path: &'static str| match mode {
+            OpenMode::Existing if !dir_path.join(path).exists() => {
+                if create.iter().any(|storage| migrate::is_file_of(path, storage)) {
+                    Ok(OpenMode::New)
+                } else {
+                    Err(OpenError::MissingStorage(path))
+                }
+            },
+            mode => Ok(mode),
+        };
         fn open_file(path: impl AsRef<Path>, mode: OpenMode) -> std::io::Result<File> {
             match mode {
                 OpenMode::New => File::options().read(true).write(true).create(true).open(path),
@@ -129,18 +289,30 @@ impl Value {
             }
         };
 
+        let mut files = Vec::new();
         macro_rules! open_file {
-            ($path: expr) => {
-                open_file(dir_path.join($path), mode).map_err(OpenError::Io)?
-            };
+            ($path: expr) => {{
+                files.push($path);
+                open_file(dir_path.join($path), storage_mode($path)?).map_err(OpenError::Io)?
+            }};
         }
 
-        Ok(Self {
+        let journal = journal::Value::new(dir_path.join("journal"));
+        let needs_recovery = journal.read().map_err(OpenError::Io)?.is_some();
+
+        let search = search::Backend::from_config(&config.search, dir_path).map_err(OpenError::Search)?;
+
+        let mut value = Self {
             config,
-            meili_client,
+            dir: dir_path.to_path_buf(),
+            files: Vec::new(),
+            lock,
+            search,
+            journal,
+            needs_recovery,
             error_sender,
             auth: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
-                mode,
+                mode: storage_mode("auth_nodes")?,
                 files: storage::binary_tree::OpenFiles {
                     nodes: open_file!("auth_nodes"),
                     free_ids: open_file!("auth_free_ids"),
@@ -152,7 +324,7 @@ impl Value {
                 },
             })?,
             users: storage::IndexedDynamic::open(storage::indexed_dynamic::OpenConfig {
-                mode,
+                mode: storage_mode("users_raw_entries")?,
                 files: storage::indexed_dynamic::OpenFiles {
                     raw_entries: open_file!("users_raw_entries"),
                     raw_free_locations: open_file!("users_raw_free_locations"),
@@ -167,7 +339,7 @@ impl Value {
                 }
             })?,
             users_name_index: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
-                mode,
+                mode: storage_mode("users_name_index_nodes")?,
                 files: storage::binary_tree::OpenFiles {
                     nodes: open_file!("users_name_index_nodes"),
                     free_ids: open_file!("users_name_index_free_ids"),
@@ -178,8 +350,80 @@ impl Value {
                     free_ids: 20,
                 },
             })?,
+            users_id_index: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("users_id_index_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("users_id_index_nodes"),
+                    free_ids: open_file!("users_id_index_free_ids"),
+                    header: open_file!("users_id_index_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 20,
+                    free_ids: 20,
+                },
+            })?,
+            users_previous_names: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("users_previous_names_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("users_previous_names_nodes"),
+                    free_ids: open_file!("users_previous_names_free_ids"),
+                    header: open_file!("users_previous_names_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 20,
+                    free_ids: 20,
+                },
+            })?,
+            users_history: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("users_history_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("users_history_nodes"),
+                    free_ids: open_file!("users_history_free_ids"),
+                    header: open_file!("users_history_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 100,
+                    free_ids: 20,
+                },
+            })?,
+            users_top_index: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("users_top_index_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("users_top_index_nodes"),
+                    free_ids: open_file!("users_top_index_free_ids"),
+                    header: open_file!("users_top_index_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 20,
+                    free_ids: 20,
+                },
+            })?,
+            users_followers: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("users_followers_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("users_followers_nodes"),
+                    free_ids: open_file!("users_followers_free_ids"),
+                    header: open_file!("users_followers_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 100,
+                    free_ids: 20,
+                },
+            })?,
+            users_following: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("users_following_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("users_following_nodes"),
+                    free_ids: open_file!("users_following_free_ids"),
+                    header: open_file!("users_following_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 100,
+                    free_ids: 20,
+                },
+            })?,
             projects: storage::IndexedDynamic::open(storage::indexed_dynamic::OpenConfig {
-                mode,
+                mode: storage_mode("projects_raw_entries")?,
                 files: storage::indexed_dynamic::OpenFiles {
                     raw_entries: open_file!("projects_raw_entries"),
                     raw_free_locations: open_file!("projects_raw_free_locations"),
@@ -194,7 +438,7 @@ impl Value {
                 }
             })?,
             projects_id_index: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
-                mode,
+                mode: storage_mode("projects_id_index_nodes")?,
                 files: storage::binary_tree::OpenFiles {
                     nodes: open_file!("projects_id_index_nodes"),
                     free_ids: open_file!("projects_id_index_free_ids"),
@@ -205,15 +449,245 @@ impl Value {
                     free_ids: 20,
                 },
             })?,
-        })
+            projects_author_index: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("projects_author_index_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("projects_author_index_nodes"),
+                    free_ids: open_file!("projects_author_index_free_ids"),
+                    header: open_file!("projects_author_index_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 20,
+                    free_ids: 20,
+                },
+            })?,
+            studios: storage::IndexedDynamic::open(storage::indexed_dynamic::OpenConfig {
+                mode: storage_mode("studios_raw_entries")?,
+                files: storage::indexed_dynamic::OpenFiles {
+                    raw_entries: open_file!("studios_raw_entries"),
+                    raw_free_locations: open_file!("studios_raw_free_locations"),
+                    indices: open_file!("studios_indices"),
+                    free_ids: open_file!("studios_free_ids")
+                },
+                max_margins: storage::indexed_dynamic::OpenMaxMargins {
+                    raw_entries: 100,
+                    raw_free_locations: 20,
+                    indices: 20,
+                    free_ids: 20,
+                }
+            })?,
+            studios_id_index: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("studios_id_index_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("studios_id_index_nodes"),
+                    free_ids: open_file!("studios_id_index_free_ids"),
+                    header: open_file!("studios_id_index_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 20,
+                    free_ids: 20,
+                },
+            })?,
+            studios_members: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("studios_members_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("studios_members_nodes"),
+                    free_ids: open_file!("studios_members_free_ids"),
+                    header: open_file!("studios_members_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 100,
+                    free_ids: 20,
+                },
+            })?,
+            studios_projects: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("studios_projects_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("studios_projects_nodes"),
+                    free_ids: open_file!("studios_projects_free_ids"),
+                    header: open_file!("studios_projects_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 100,
+                    free_ids: 20,
+                },
+            })?,
+            forum_topics: storage::IndexedDynamic::open(storage::indexed_dynamic::OpenConfig {
+                mode: storage_mode("forum_topics_raw_entries")?,
+                files: storage::indexed_dynamic::OpenFiles {
+                    raw_entries: open_file!("forum_topics_raw_entries"),
+                    raw_free_locations: open_file!("forum_topics_raw_free_locations"),
+                    indices: open_file!("forum_topics_indices"),
+                    free_ids: open_file!("forum_topics_free_ids")
+                },
+                max_margins: storage::indexed_dynamic::OpenMaxMargins {
+                    raw_entries: 100,
+                    raw_free_locations: 20,
+                    indices: 20,
+                    free_ids: 20,
+                }
+            })?,
+            forum_topics_id_index: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("forum_topics_id_index_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("forum_topics_id_index_nodes"),
+                    free_ids: open_file!("forum_topics_id_index_free_ids"),
+                    header: open_file!("forum_topics_id_index_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 20,
+                    free_ids: 20,
+                },
+            })?,
+            forum_topics_category_index: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("forum_topics_category_index_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("forum_topics_category_index_nodes"),
+                    free_ids: open_file!("forum_topics_category_index_free_ids"),
+                    header: open_file!("forum_topics_category_index_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 20,
+                    free_ids: 20,
+                },
+            })?,
+            forum_posts: storage::IndexedDynamic::open(storage::indexed_dynamic::OpenConfig {
+                mode: storage_mode("forum_posts_raw_entries")?,
+                files: storage::indexed_dynamic::OpenFiles {
+                    raw_entries: open_file!("forum_posts_raw_entries"),
+                    raw_free_locations: open_file!("forum_posts_raw_free_locations"),
+                    indices: open_file!("forum_posts_indices"),
+                    free_ids: open_file!("forum_posts_free_ids")
+                },
+                max_margins: storage::indexed_dynamic::OpenMaxMargins {
+                    raw_entries: 100,
+                    raw_free_locations: 20,
+                    indices: 20,
+                    free_ids: 20,
+                }
+            })?,
+            forum_posts_id_index: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("forum_posts_id_index_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("forum_posts_id_index_nodes"),
+                    free_ids: open_file!("forum_posts_id_index_free_ids"),
+                    header: open_file!("forum_posts_id_index_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 20,
+                    free_ids: 20,
+                },
+            })?,
+            forum_posts_topic_index: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("forum_posts_topic_index_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("forum_posts_topic_index_nodes"),
+                    free_ids: open_file!("forum_posts_topic_index_free_ids"),
+                    header: open_file!("forum_posts_topic_index_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 20,
+                    free_ids: 20,
+                },
+            })?,
+            comments: storage::IndexedDynamic::open(storage::indexed_dynamic::OpenConfig {
+                mode: storage_mode("comments_raw_entries")?,
+                files: storage::indexed_dynamic::OpenFiles {
+                    raw_entries: open_file!("comments_raw_entries"),
+                    raw_free_locations: open_file!("comments_raw_free_locations"),
+                    indices: open_file!("comments_indices"),
+                    free_ids: open_file!("comments_free_ids")
+                },
+                max_margins: storage::indexed_dynamic::OpenMaxMargins {
+                    raw_entries: 100,
+                    raw_free_locations: 20,
+                    indices: 20,
+                    free_ids: 20,
+                }
+            })?,
+            comments_id_index: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("comments_id_index_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("comments_id_index_nodes"),
+                    free_ids: open_file!("comments_id_index_free_ids"),
+                    header: open_file!("comments_id_index_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 20,
+                    free_ids: 20,
+                },
+            })?,
+            comments_thread_index: storage::BinaryTree::open(storage::binary_tree::OpenConfig {
+                mode: storage_mode("comments_thread_index_nodes")?,
+                files: storage::binary_tree::OpenFiles {
+                    nodes: open_file!("comments_thread_index_nodes"),
+                    free_ids: open_file!("comments_thread_index_free_ids"),
+                    header: open_file!("comments_thread_index_header"),
+                },
+                max_margins: storage::binary_tree::OpenMaxMargins {
+                    nodes: 20,
+                    free_ids: 20,
+                },
+            })?,
+        };
+        value.files = files;
+        Ok(value)
+    }
+
+    async fn send_error(&self, err: InternalError) {
+        let _ = self.error_sender.send(err).await;
     }
 
     async fn send_bindb_error(&self, op: BindbErrorOp, kind: BindbErrorKind) {
         let _ = self.error_sender.send(InternalError::bindb(op, kind)).await;
     }
 
-    async fn send_meili_error(&self, err: meilisearch_sdk::errors::Error) {
-        let _ = self.error_sender.send(InternalError::Meili(err)).await;
+    async fn send_search_error(&self, err: search::Error) {
+        let _ = self.error_sender.send(InternalError::Search(err)).await;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{borrow::Cow, path::{Path, PathBuf}};
+    use tokio::sync::mpsc;
+    use crate::{config, manifest, user};
+
+    // An empty folder under the temp dir, cleared first if an earlier run left it behind.
+    pub fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("meowstore_test_{name}"));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    pub fn config() -> config::Root {
+        config::Root {
+            require_auth: config::RequireAuth { read: false, write: false, remove: false },
+            search: config::Search::Embedded,
+        }
+    }
+
+    // Creates the database in `dir` if it doesn't hold one yet, then opens it. Internal errors are dropped.
+    pub async fn open(dir: &Path) -> crate::Value {
+        if manifest::read(dir).unwrap().is_none() {
+            manifest::create(dir, config::Create {}).unwrap();
+        }
+        let (error_sender, _) = mpsc::channel(20);
+        unsafe { crate::Value::open(dir, config(), error_sender) }.await.unwrap()
+    }
+
+    pub fn user(name: &str, id: u64) -> user::Value<'static> {
+        user::Value {
+            name: name.parse().unwrap(),
+            id,
+            scratch_team: false,
+            status: Cow::Borrowed("status"),
+            bio: Cow::Borrowed("bio"),
+            loves: 0,
+            favorites: 0,
+            views: 0,
+            remixes: 0,
+        }
     }
 }
 
@@ -0,0 +1,86 @@
+use std::{fs::{self, File, TryLockError}, io::{self, Write}, path::Path};
+use crate::{config, OpenError, FORMAT_VERSION};
+
+// Describes the database folder. Replaced as a whole, so it's never half written.
+pub const MANIFEST_FILE: &str = "manifest.json";
+// Locked by the process that has the database open.
+pub const LOCK_FILE: &str = "lock";
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    pub format_version: u32,
+    // Unix timestamp (seconds).
+    pub created: i64,
+    // Names of the storage files, empty until the database is opened for the first time.
+    pub storages: Vec<String>,
+    pub create: config::Create,
+}
+
+impl Manifest {
+    pub fn new(create: config::Create) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            created: chrono::Utc::now().timestamp(),
+            storages: Vec::new(),
+            create,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Invalid(serde_json::Error),
+}
+
+#[derive(Debug)]
+pub enum CreateError {
+    Io(io::Error),
+    // The folder already holds a database.
+    Exists,
+}
+
+// `None` if the folder has no manifest, like databases from before it was added.
+pub fn read(dir_path: &Path) -> Result<Option<Manifest>, Error> {
+    match fs::read(dir_path.join(MANIFEST_FILE)) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map(Some).map_err(Error::Invalid),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+pub(crate) fn write(dir_path: &Path, manifest: &Manifest) -> io::Result<()> {
+    let tmp_path = dir_path.join(format!("{MANIFEST_FILE}.tmp"));
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(&serde_json::to_vec_pretty(manifest).map_err(io::Error::from)?)?;
+    file.sync_all()?;
+    fs::rename(tmp_path, dir_path.join(MANIFEST_FILE))
+}
+
+// Sets up the folder of a new database, its storages are created when it's first opened.
+pub fn create(dir_path: &Path, create: config::Create) -> Result<Manifest, CreateError> {
+    fs::create_dir_all(dir_path).map_err(CreateError::Io)?;
+    if dir_path.join(MANIFEST_FILE).exists() {
+        Err(CreateError::Exists)?
+    }
+    let manifest = Manifest::new(create);
+    write(dir_path, &manifest).map_err(CreateError::Io)?;
+    Ok(manifest)
+}
+
+// Locks the folder for as long as the returned file is open.
+// The lock is released by the OS, so it doesn't outlive a crashed process.
+pub(crate) fn lock(dir_path: &Path) -> Result<File, OpenError> {
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dir_path.join(LOCK_FILE))
+        .map_err(OpenError::Io)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(OpenError::Locked),
+        Err(TryLockError::Error(e)) => Err(OpenError::Io(e)),
+    }
+}
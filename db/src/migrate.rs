@@ -0,0 +1,226 @@
+use std::{collections::BTreeMap, fs, io, path::Path};
+use bindb::storage::OpenMode;
+use tokio::sync::mpsc;
+use crate::{check, config, manifest, search, BindbErrorOp, InternalError, OpenError, Username, FORMAT_VERSION};
+
+// Migrates the layout of version `from` to `from + 1`.
+pub struct Step {
+    pub from: u32,
+    pub desc: &'static str,
+    // Runs on the database opened with the storages it lacks created empty.
+    run: unsafe fn(&mut crate::Value) -> Result<(), Error>,
+    // Storages the step adds, they are created empty and filled from the stored entries.
+    // Opening a database that lacks any other storage fails.
+    adds: &'static [&'static str],
+    // Storages deleted after `run`, they are rebuilt from the stored entries.
+    // For indexes whose key layout or order changed.
+    rebuild: &'static [&'static str],
+}
+
+// Every step, ordered by `from`. The last one migrates to `FORMAT_VERSION`.
+pub const STEPS: &[Step] = &[
+    Step {
+        from: 0,
+        desc: "index users by id and by their statistics, key the name index by case folded names, add the project author, studio, forum, comment and follow storages",
+        run: index_users,
+        adds: &[
+            "users_id_index", "users_previous_names", "users_history", "users_top_index", "users_followers", "users_following",
+            "projects_author_index",
+            "studios", "studios_id_index", "studios_members", "studios_projects",
+            "forum_topics", "forum_topics_id_index", "forum_topics_category_index",
+            "forum_posts", "forum_posts_id_index", "forum_posts_topic_index",
+            "comments", "comments_id_index", "comments_thread_index",
+        ],
+        rebuild: &["users_name_index"],
+    },
+];
+
+// Suffixes of the files a storage is made of.
+const FILE_SUFFIXES: [&str; 6] = ["_nodes", "_free_ids", "_header", "_indices", "_raw_entries", "_raw_free_locations"];
+
+// Whether `file` is one of the files of `storage`.
+pub(crate) fn is_file_of(file: &str, storage: &str) -> bool {
+    file.strip_prefix(storage).is_some_and(|suffix| FILE_SUFFIXES.contains(&suffix))
+}
+
+// The unversioned layout could only find users by name, and had no leaderboards.
+// Its names were case sensitive, names that only differ in case are refused before anything is written.
+unsafe fn index_users(db: &mut crate::Value) -> Result<(), Error> {
+    let ids = db.users_name_index.iter().map(|(_, id)| id.unwrap()).collect::<Vec<_>>();
+    let mut by_folded_name = BTreeMap::<Username, Vec<Username>>::new();
+    for id in &ids {
+        let name = db.users.get(*id).fixed_data.name;
+        by_folded_name.entry(name.folded()).or_default().push(name);
+    }
+    let collisions = by_folded_name.into_values().filter(|names| names.len() > 1).collect::<Vec<_>>();
+    if !collisions.is_empty() {
+        Err(Error::NameCollisions(collisions))?
+    }
+
+    let e = |kind| Error::Internal(InternalError::bindb(BindbErrorOp::Migrate, kind));
+    for id in ids {
+        let data = db.users.get(id).fixed_data;
+        db.set_user_id_index(data.id, id).map_err(e)?;
+        db.set_user_top_index(&data, id).map_err(e)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Manifest(manifest::Error),
+    // The database was written by a newer version.
+    Newer { found: u32 },
+    // The journal holds an unfinished write, it must be recovered with the version that wrote it.
+    NeedsRecovery,
+    // Names of users that only differ in case, which would share a key of the name index.
+    // All but one of each have to be removed with the version that wrote the database.
+    NameCollisions(Vec<Vec<Username>>),
+    Open(OpenError),
+    Search(search::Error),
+    Internal(InternalError),
+}
+
+#[derive(Debug)]
+pub struct Migrated {
+    pub from: u32,
+    // Descriptions of the steps that ran, none if the database was up to date.
+    pub steps: Vec<&'static str>,
+    // Index entries and search documents that were repaired after the steps.
+    pub report: check::Report,
+}
+
+// Runs every step from the recorded version up to `FORMAT_VERSION`, then fills the rebuilt and new storages
+// from the stored entries and reindexes search. The manifest is written last, so an interrupted migration can be run again.
+// Databases without a manifest are version 0, `create` is recorded in the manifest written for them.
+pub async fn migrate(
+    dir_path: &Path,
+    create: config::Create,
+    config: config::Root,
+    error_sender: mpsc::Sender<InternalError>,
+) -> Result<Migrated, Error> {
+    let lock = manifest::lock(dir_path).map_err(Error::Open)?;
+    let old_manifest = manifest::read(dir_path).map_err(Error::Manifest)?;
+    let from = old_manifest.as_ref().map_or(0, |manifest| manifest.format_version);
+    if from > FORMAT_VERSION {
+        Err(Error::Newer { found: from })?
+    }
+    let steps = STEPS.iter().filter(|step| step.from >= from).collect::<Vec<_>>();
+    if steps.is_empty() {
+        return Ok(Migrated { from, steps: Vec::new(), report: check::Report::default() });
+    }
+
+    let added = steps.iter().flat_map(|step| step.adds).copied().collect::<Vec<_>>();
+    let rebuild = steps.iter().flat_map(|step| step.rebuild).copied().collect::<Vec<_>>();
+
+    // Only the lock is kept, the storages are closed before the rebuilt ones are deleted.
+    let (rebuilt, lock) = {
+        let mut db = unsafe {
+            crate::Value::open_storages(dir_path, OpenMode::Existing, &added, lock, config.clone(), error_sender.clone())
+        }.map_err(Error::Open)?;
+        if db.needs_recovery {
+            Err(Error::NeedsRecovery)?
+        }
+        for step in &steps {
+            unsafe { (step.run)(&mut db) }?;
+        }
+        let rebuilt = db.files.iter()
+            .filter(|file| rebuild.iter().any(|storage| is_file_of(file, storage)))
+            .copied()
+            .collect::<Vec<_>>();
+        (rebuilt, db.lock)
+    };
+    for file in rebuilt {
+        fs::remove_file(dir_path.join(file)).map_err(Error::Io)?;
+    }
+
+    let mut db = unsafe {
+        crate::Value::open_storages(dir_path, OpenMode::Existing, &rebuild, lock, config, error_sender)
+    }.map_err(Error::Open)?;
+    db.init_search().await.map_err(Error::Search)?;
+    let report = db.check(true).await.map_err(Error::Internal)?;
+    db.reindex_search(|_| {}).await.map_err(Error::Search)?;
+
+    let mut manifest = old_manifest.unwrap_or_else(|| manifest::Manifest::new(create));
+    manifest.format_version = FORMAT_VERSION;
+    manifest.storages = db.files.iter().map(|name| name.to_string()).collect();
+    manifest::write(dir_path, &manifest).map_err(Error::Io)?;
+    Ok(Migrated { from, steps: steps.iter().map(|step| step.desc).collect(), report })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use tokio::sync::mpsc;
+    use crate::{config::Create, manifest::MANIFEST_FILE, tests::{config, dir, open, user}, OpenError};
+    use std::path::Path;
+    use binbuf::impls::ArbNum;
+    use super::{is_file_of, migrate, Error, STEPS};
+
+    // Turns the database into one from before the manifest, without the storages added since.
+    fn remove_added_storages(dir: &Path) {
+        fs::remove_file(dir.join(MANIFEST_FILE)).unwrap();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let file = path.file_name().unwrap().to_str().unwrap().to_string();
+            if STEPS.iter().flat_map(|step| step.adds).any(|storage| is_file_of(&file, storage)) {
+                fs::remove_file(path).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn migrate_creates_added_storages() {
+        let dir = dir("migrate_added");
+        let mut db = open(&dir).await;
+        db.add_user(None, user("griffpatch", 1)).await.unwrap();
+        drop(db);
+        remove_added_storages(&dir);
+
+        let (error_sender, _) = mpsc::channel(20);
+        let migrated = migrate(&dir, Create {}, config(), error_sender).await.unwrap();
+        assert_eq!(migrated.from, 0);
+        let db = open(&dir).await;
+        assert_eq!(db.user_by_id(None, &1).unwrap().name, "griffpatch".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn migrate_refuses_names_differing_in_case() {
+        let dir = dir("migrate_name_collision");
+        let mut db = open(&dir).await;
+        // The unversioned name index was keyed by the names as they are.
+        for (name, id) in [("Kaj", 1), ("kaj", 2)] {
+            let entry = unsafe { db.users.add(&user(name, id).to_db_value()) }.unwrap();
+            unsafe { db.users_name_index.add(&name.parse().unwrap(), &ArbNum::new(entry)) }.unwrap();
+        }
+        drop(db);
+        remove_added_storages(&dir);
+
+        let (error_sender, _) = mpsc::channel(20);
+        let res = migrate(&dir, Create {}, config(), error_sender).await;
+        let Err(Error::NameCollisions(collisions)) = res else {
+            panic!("expected a name collision, got {res:?}");
+        };
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].len(), 2);
+    }
+
+    #[tokio::test]
+    async fn open_reports_missing_storage() {
+        let dir = dir("migrate_missing");
+        drop(open(&dir).await);
+        fs::remove_file(dir.join("users_history_nodes")).unwrap();
+
+        let (error_sender, _) = mpsc::channel(20);
+        let res = unsafe { crate::Value::open(&dir, config(), error_sender) }.await;
+        assert!(matches!(res, Err(OpenError::MissingStorage("users_history_nodes"))));
+    }
+
+    #[test]
+    fn files_belong_to_one_storage() {
+        assert!(is_file_of("studios_raw_entries", "studios"));
+        assert!(!is_file_of("studios_members_nodes", "studios"));
+        assert!(is_file_of("studios_members_nodes", "studios_members"));
+    }
+}
@@ -1,6 +1,6 @@
 use bitflags::bitflags;
-use crate::{auth, BindbErrorKind, BindbErrorOp};
-use binbuf::impls::ArbNum;
+use bindb::storage::IndexedDynamic;
+use crate::{auth, entity::{set_index, unset_index, Entity}, journal, list_limit, search::{self, FilterValue, SearchBackend}, BindbErrorKind, BindbErrorOp};
 use super::Username;
 use binbuf::impls::dynamic::StringCLL;
 
@@ -113,14 +113,94 @@ impl Value {
     }
 }
 
+// Key of a project in the author index. Authors are identified by their Scratch id, so it survives renames.
+binbuf::fixed! {
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct AuthorKey {
+        pub author_id: u64,
+        pub project_id: u64,
+    }
+
+    buf! { pub struct AuthorKeyBuf<P>(AuthorKey, P); }
+
+    impl I for AuthorKey {
+        type Buf<P> = AuthorKeyBuf<P>;
+    }
+    impl Code for AuthorKey {}
+}
+
+impl AuthorKey {
+    pub fn new(db_repr: &DbRepr) -> Self {
+        Self {
+            author_id: db_repr.author_id,
+            project_id: db_repr.id,
+        }
+    }
+}
+
+// A page of an author's projects sorted by id, starting right after the `after` project id.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct ByAuthorQuery {
+    pub after: Option<u64>,
+    pub limit: Option<u32>,
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct MeiliDoc {
     pub id: u64,
+    pub author_id: u64,
+    pub author_name: String,
+    pub public: bool,
+    pub is_published: bool,
     pub title: String,
     pub description: String,
     pub instructions: String,
 }
 
+impl MeiliDoc {
+    pub fn new(id: u64, value: Value) -> Self {
+        Self {
+            id,
+            author_id: value.author_id,
+            author_name: value.author_name.to_string(),
+            public: value.public,
+            is_published: value.is_published,
+            title: value.title,
+            description: value.description,
+            instructions: value.instructions,
+        }
+    }
+
+    pub const FILTERABLE: [&'static str; 4] = ["author_id", "author_name", "public", "is_published"];
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct SearchFilter {
+    pub author_id: Option<u64>,
+    pub author_name: Option<Username>,
+    pub public: Option<bool>,
+    pub is_published: Option<bool>,
+}
+
+impl SearchFilter {
+    fn to_conditions(&self) -> Vec<search::Condition> {
+        let mut conditions = Vec::new();
+        if let Some(author_id) = self.author_id {
+            conditions.push(("author_id", FilterValue::U64(author_id)));
+        }
+        if let Some(author_name) = &self.author_name {
+            conditions.push(("author_name", FilterValue::String(author_name.to_string())));
+        }
+        if let Some(public) = self.public {
+            conditions.push(("public", FilterValue::Bool(public)));
+        }
+        if let Some(is_published) = self.is_published {
+            conditions.push(("is_published", FilterValue::Bool(is_published)));
+        }
+        conditions
+    }
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
 pub enum GetByIdError {
     Auth(auth::EnsureAuthError),
@@ -134,7 +214,88 @@ pub enum AddError {
     BadInput(ToDbReprError),
 }
 
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum SearchError {
+    Internal,
+    Auth(auth::EnsureAuthError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum RemoveByIdError {
+    Internal,
+    Auth(auth::EnsureAuthError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ByAuthorError {
+    Auth(auth::EnsureAuthError)
+}
+
+impl Entity for DbRepr {
+    const ADD_OP: BindbErrorOp = BindbErrorOp::AddProject;
+    const REPLACE_OP: BindbErrorOp = BindbErrorOp::UpsertProject;
+    const REMOVE_OP: BindbErrorOp = BindbErrorOp::RemoveProjectById;
+    const SEARCH_INDEX: Option<&'static str> = Some("projects");
+    type Doc = MeiliDoc;
+
+    fn storage(db: &crate::Value) -> &IndexedDynamic<Self> {
+        &db.projects
+    }
+
+    fn storage_mut(db: &mut crate::Value) -> &mut IndexedDynamic<Self> {
+        &mut db.projects
+    }
+
+    fn journal_entry(id: u64) -> journal::Entry {
+        journal::Entry::Project(id)
+    }
+
+    fn add_intent(id: u64) -> journal::Intent {
+        journal::Intent::AddProject { id }
+    }
+
+    fn replace_intent(old_id: u64, id: u64) -> journal::Intent {
+        journal::Intent::ReplaceProject { old_id, id }
+    }
+
+    fn remove_intent(id: u64) -> journal::Intent {
+        journal::Intent::RemoveProject { id }
+    }
+
+    fn doc(id: u64, data: Self) -> MeiliDoc {
+        MeiliDoc::new(id, data.into())
+    }
+
+    unsafe fn set_indexes(db: &mut crate::Value, data: &Self, id: u64) -> Result<(), BindbErrorKind> {
+        set_index(&mut db.projects_id_index, &data.id, id)?;
+        set_index(&mut db.projects_author_index, &AuthorKey::new(data), id)
+    }
+
+    unsafe fn unset_indexes(db: &mut crate::Value, data: &Self, id: u64) -> Result<(), BindbErrorKind> {
+        unset_index(&mut db.projects_id_index, &data.id, id)?;
+        unset_index(&mut db.projects_author_index, &AuthorKey::new(data), id)
+    }
+}
+
 impl super::Value {
+    pub(crate) fn projects_by_author_raw(&self, author_id: u64, query: &ByAuthorQuery) -> Vec<Value> {
+        let Some(project_id) = query.after.map_or(Some(0), |after| after.checked_add(1)) else {
+            return Vec::new();
+        };
+        iter_from!(self.projects_author_index, AuthorKey { author_id, project_id })
+            .take_while(|(key, _)| key.author_id == author_id)
+            .take(list_limit(query.limit))
+            .map(|(_, id)| self.projects.get(id.get()).into())
+            .collect()
+    }
+
+    // Projects of the author with this Scratch id, sorted by project id.
+    // The id of the last project in a page is the `after` cursor of the next page.
+    pub fn projects_by_author(&self, auth_key: Option<&auth::Key>, author_id: u64, query: &ByAuthorQuery) -> Result<Vec<Value>, ByAuthorError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(ByAuthorError::Auth)?;
+        Ok(self.projects_by_author_raw(author_id, query))
+    }
+
     pub fn project_by_id(&self, auth_key: Option<&auth::Key>, id: &u64) -> Result<Value, GetByIdError> {
         self.ensure_auth(auth::Op::Read, auth_key).map_err(GetByIdError::Auth)?;
         self.projects_id_index.get(id)
@@ -144,52 +305,59 @@ impl super::Value {
             .ok_or(GetByIdError::NotFound)
     }
 
+    // Returns true if already exists.
     pub async fn add_project(&mut self, auth_key: Option<&auth::Key>, value: Value) -> Result<bool, AddError> {
         self.ensure_auth(auth::Op::Write, auth_key).map_err(AddError::Auth)?;
-        let searched = self.projects_id_index.search(&value.id);
-        match searched.find() {
-            Ok(_) => Ok(true),
-            Err(searched) => {
-                let value_id = value.id;
-
-                let db_repr = value.clone().to_db_repr().map_err(AddError::BadInput)?;
-                let id = match unsafe { self.projects.add(&db_repr) } {
-                    Ok(id) => id,
-                    Err(e) => {
-                        self.send_bindb_error(BindbErrorOp::AddProject, BindbErrorKind::IndexedDynamicAdd(e)).await;
-                        Err(AddError::Internal)?
-                    }
-                };
-
-                let meili_doc = MeiliDoc {
-                    id,
-                    title: value.title,
-                    description: value.description,
-                    instructions: value.instructions,
-                };
-
-                if let Err(e) = unsafe {
-                    self.projects_id_index.add_searched(&searched, &value_id, &ArbNum::new(id))
-                } {
-                    self.send_bindb_error(BindbErrorOp::AddProject, BindbErrorKind::BinaryTreeAdd(e)).await;
-                    Err(AddError::Internal)?
-                }
-                
-                let info = match self.meili_client
-                    .index("projects")
-                    .add_documents(&[meili_doc], None)
-                    .await
-                {
-                    Ok(info) => info,
-                    Err(e) => {
-                        self.send_meili_error(e).await;
-                        Err(AddError::Internal)?
-                    }
-                };
-                println!("Task info: {:?}", info);
-
-                Ok(false)
+        self.ensure_recovered().await.map_err(|_| AddError::Internal)?;
+        if self.projects_id_index.get(&value.id).is_some() {
+            return Ok(true);
+        }
+
+        let db_repr = value.to_db_repr().map_err(AddError::BadInput)?;
+        self.write_add(&db_repr).await.map_err(|_| AddError::Internal)?;
+        Ok(false)
+    }
+
+    // Inserts the project, or overwrites the existing project with the same id.
+    // Returns true if already exists.
+    pub async fn upsert_project(&mut self, auth_key: Option<&auth::Key>, value: Value) -> Result<bool, AddError> {
+        self.ensure_auth(auth::Op::Write, auth_key).map_err(AddError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| AddError::Internal)?;
+        let old_id = match self.projects_id_index.get(&value.id) {
+            Some(old_id) => old_id.get(),
+            None => return self.add_project(auth_key, value).await,
+        };
+
+        let db_repr = value.to_db_repr().map_err(AddError::BadInput)?;
+        self.write_replace(old_id, &db_repr).await.map_err(|_| AddError::Internal)?;
+        Ok(true)
+    }
+
+    pub async fn search_projects(&self, auth_key: Option<&auth::Key>, query: &str, filter: &SearchFilter) -> Result<Vec<Value>, SearchError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(SearchError::Auth)?;
+        let ids = match self.search.search("projects", query, &filter.to_conditions()).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                self.send_search_error(e).await;
+                Err(SearchError::Internal)?
             }
+        };
+
+        let mut hits = Vec::with_capacity(ids.len());
+        for id in ids {
+            hits.push(Value::from(self.projects.get(id)));
         }
+        Ok(hits)
     }
-}
\ No newline at end of file
+
+    // Returns true if didn't exist.
+    pub async fn remove_project_by_id(&mut self, auth_key: Option<&auth::Key>, id: &u64) -> Result<bool, RemoveByIdError> {
+        self.ensure_auth(auth::Op::Remove, auth_key).map_err(RemoveByIdError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| RemoveByIdError::Internal)?;
+        let Some(id) = self.projects_id_index.get(id) else {
+            return Ok(true);
+        };
+        self.write_remove::<DbRepr>(id.get()).await.map_err(|_| RemoveByIdError::Internal)?;
+        Ok(false)
+    }
+}
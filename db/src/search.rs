@@ -0,0 +1,234 @@
+use std::{future::Future, path::Path};
+use serde::Serialize;
+use crate::{comment, config, forum, project, studio, user};
+
+pub mod meili;
+pub mod embedded;
+
+#[derive(Debug)]
+pub enum Error {
+    Meili(meilisearch_sdk::errors::Error),
+    Json(serde_json::Error),
+    Io(std::io::Error),
+    // A document must serialize to an object with an integer `id` field.
+    InvalidDocument,
+}
+
+#[derive(Clone, Debug)]
+pub enum FilterValue {
+    U64(u64),
+    Bool(bool),
+    String(String),
+}
+
+// An equality condition on a document field. Conditions of a filter are combined with AND.
+pub type Condition = (&'static str, FilterValue);
+
+// The documents of an index are identified by the `id` field,
+// which is the id of the entry in the corresponding bindb storage.
+// The futures are `Send`, so database calls can be spawned on a multithreaded runtime.
+// Implementations can still use `async fn`.
+pub trait SearchBackend {
+    // Adds the documents, replacing ones with the same ids.
+    fn index<T: Serialize + Send + Sync>(&self, index: &str, docs: &[T]) -> impl Future<Output = Result<(), Error>> + Send;
+    fn delete(&self, index: &str, id: u64) -> impl Future<Output = Result<(), Error>> + Send;
+    fn delete_all(&self, index: &str) -> impl Future<Output = Result<(), Error>> + Send;
+    // Returns ids of the matching documents, best matches first.
+    fn search(&self, index: &str, query: &str, filter: &[Condition]) -> impl Future<Output = Result<Vec<u64>, Error>> + Send;
+    // Must be called for every field used in filters.
+    fn set_filterable(&self, index: &str, fields: &[&str]) -> impl Future<Output = Result<(), Error>> + Send;
+    // Ids of every document in the index.
+    fn ids(&self, index: &str) -> impl Future<Output = Result<Vec<u64>, Error>> + Send;
+}
+
+pub enum Backend {
+    Meili(meili::Value),
+    Embedded(embedded::Value),
+}
+
+impl Backend {
+    // The embedded backend is stored in `{DIR_PATH}/search`.
+    pub fn from_config(config: &config::Search, dir_path: &Path) -> Result<Self, Error> {
+        Ok(match config {
+            config::Search::Meili { host, key } => Self::Meili(meili::Value::new(host, key)?),
+            config::Search::Embedded => Self::Embedded(embedded::Value::open(dir_path.join("search"))?),
+        })
+    }
+}
+
+impl SearchBackend for Backend {
+    async fn index<T: Serialize + Send + Sync>(&self, index: &str, docs: &[T]) -> Result<(), Error> {
+        match self {
+            Self::Meili(backend) => backend.index(index, docs).await,
+            Self::Embedded(backend) => backend.index(index, docs).await,
+        }
+    }
+
+    async fn delete(&self, index: &str, id: u64) -> Result<(), Error> {
+        match self {
+            Self::Meili(backend) => backend.delete(index, id).await,
+            Self::Embedded(backend) => backend.delete(index, id).await,
+        }
+    }
+
+    async fn delete_all(&self, index: &str) -> Result<(), Error> {
+        match self {
+            Self::Meili(backend) => backend.delete_all(index).await,
+            Self::Embedded(backend) => backend.delete_all(index).await,
+        }
+    }
+
+    async fn search(&self, index: &str, query: &str, filter: &[Condition]) -> Result<Vec<u64>, Error> {
+        match self {
+            Self::Meili(backend) => backend.search(index, query, filter).await,
+            Self::Embedded(backend) => backend.search(index, query, filter).await,
+        }
+    }
+
+    async fn set_filterable(&self, index: &str, fields: &[&str]) -> Result<(), Error> {
+        match self {
+            Self::Meili(backend) => backend.set_filterable(index, fields).await,
+            Self::Embedded(backend) => backend.set_filterable(index, fields).await,
+        }
+    }
+
+    async fn ids(&self, index: &str) -> Result<Vec<u64>, Error> {
+        match self {
+            Self::Meili(backend) => backend.ids(index).await,
+            Self::Embedded(backend) => backend.ids(index).await,
+        }
+    }
+}
+
+pub const REINDEX_BATCH_LEN: usize = 1000;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ReindexProgress {
+    pub index: &'static str,
+    // Number of documents pushed so far.
+    pub done: usize,
+}
+
+async fn push_in_batches<T: Serialize + Send + Sync>(
+    backend: &Backend,
+    index: &'static str,
+    docs: impl Iterator<Item = T>,
+    on_progress: &mut impl FnMut(ReindexProgress),
+) -> Result<(), Error> {
+    let mut batch = Vec::with_capacity(REINDEX_BATCH_LEN);
+    let mut done = 0;
+    for doc in docs {
+        batch.push(doc);
+        if batch.len() == REINDEX_BATCH_LEN {
+            backend.index(index, &batch).await?;
+            done += batch.len();
+            batch.clear();
+            on_progress(ReindexProgress { index, done });
+        }
+    }
+    if !batch.is_empty() {
+        backend.index(index, &batch).await?;
+        done += batch.len();
+    }
+    on_progress(ReindexProgress { index, done });
+    Ok(())
+}
+
+impl super::Value {
+    // Must be called before searching with filters.
+    pub async fn init_search(&self) -> Result<(), Error> {
+        self.search.set_filterable("projects", &project::MeiliDoc::FILTERABLE).await?;
+        self.search.set_filterable("forum_posts", &forum::post::MeiliDoc::FILTERABLE).await?;
+        self.search.set_filterable("comments", &comment::MeiliDoc::FILTERABLE).await
+    }
+
+    // Replaces all documents in the search indexes with ones built from the stored users, projects, studios, forum posts and comments.
+    pub async fn reindex_search(&self, mut on_progress: impl FnMut(ReindexProgress)) -> Result<(), Error> {
+        self.search.delete_all("users").await?;
+        let docs = self.users_name_index.iter().map(|(_, id)| {
+            let id = id.unwrap();
+            user::MeiliDoc::from_db_value(id, self.users.get(id))
+        });
+        push_in_batches(&self.search, "users", docs, &mut on_progress).await?;
+
+        self.search.delete_all("projects").await?;
+        let docs = self.projects_id_index.iter().map(|(_, id)| {
+            let id = id.get();
+            project::MeiliDoc::new(id, self.projects.get(id).into())
+        });
+        push_in_batches(&self.search, "projects", docs, &mut on_progress).await?;
+
+        self.search.delete_all("studios").await?;
+        let docs = self.studios_id_index.iter().map(|(_, id)| {
+            let id = id.get();
+            studio::MeiliDoc::new(id, self.studios.get(id).into())
+        });
+        push_in_batches(&self.search, "studios", docs, &mut on_progress).await?;
+
+        self.search.delete_all("forum_posts").await?;
+        let docs = self.forum_posts_id_index.iter().map(|(_, id)| {
+            let id = id.get();
+            forum::post::MeiliDoc::new(id, self.forum_posts.get(id).into())
+        });
+        push_in_batches(&self.search, "forum_posts", docs, &mut on_progress).await?;
+
+        self.search.delete_all("comments").await?;
+        let docs = self.comments_id_index.iter().map(|(_, id)| {
+            let id = id.get();
+            comment::MeiliDoc::new(id, self.comments.get(id))
+        });
+        push_in_batches(&self.search, "comments", docs, &mut on_progress).await?;
+
+        self.init_search().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{tests::{dir, open, user}, config};
+    use super::{Backend, FilterValue, SearchBackend};
+
+    #[derive(serde::Serialize)]
+    struct Doc {
+        id: u64,
+        title: &'static str,
+        shared: bool,
+    }
+
+    #[tokio::test]
+    async fn embedded_backend_persists_documents() {
+        let dir = dir("search_embedded");
+        let backend = Backend::from_config(&config::Search::Embedded, &dir).unwrap();
+        let docs = [
+            Doc { id: 1, title: "Paper Minecraft", shared: true },
+            Doc { id: 2, title: "Minecraft platformer", shared: false },
+        ];
+        backend.index("projects", &docs).await.unwrap();
+        backend.set_filterable("projects", &["shared"]).await.unwrap();
+        assert_eq!(backend.search("projects", "minecraft", &[]).await.unwrap(), [1, 2]);
+        assert_eq!(backend.search("projects", "minecraft", &[("shared", FilterValue::Bool(false))]).await.unwrap(), [2]);
+        backend.delete("projects", 1).await.unwrap();
+        drop(backend);
+
+        let backend = Backend::from_config(&config::Search::Embedded, &dir).unwrap();
+        assert_eq!(backend.ids("projects").await.unwrap(), [2]);
+        assert!(backend.search("projects", "paper", &[]).await.unwrap().is_empty());
+        backend.delete_all("projects").await.unwrap();
+        assert!(backend.ids("projects").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn users_are_searchable_and_reindexed() {
+        let dir = dir("search_users");
+        let mut db = open(&dir).await;
+        db.add_user(None, user("griffpatch", 1)).await.unwrap();
+        db.add_user(None, user("kaj", 2)).await.unwrap();
+        let names = |users: Vec<crate::User<'static>>| users.into_iter().map(|user| user.name.to_string()).collect::<Vec<_>>();
+        assert_eq!(names(db.search_users(None, "griff").await.unwrap()), ["griffpatch"]);
+
+        db.search.delete_all("users").await.unwrap();
+        assert!(db.search_users(None, "griff").await.unwrap().is_empty());
+        db.reindex_search(|_| {}).await.unwrap();
+        assert_eq!(names(db.search_users(None, "griff").await.unwrap()), ["griffpatch"]);
+    }
+}
@@ -0,0 +1,471 @@
+use std::{collections::{BTreeMap, HashMap}, fs::{self, File}, io::{self, BufWriter, Write}, path::PathBuf, sync::RwLock};
+use serde::Serialize;
+use serde_json::{Map, Value as Json};
+use super::{Condition, Error, FilterValue, SearchBackend};
+
+// Same as the default limit of meilisearch.
+pub const SEARCH_LIMIT: usize = 20;
+
+// Lowercased alphanumeric words.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+// Every string field of a document is searchable.
+fn doc_terms(doc: &Map<String, Json>) -> impl Iterator<Item = String> + '_ {
+    doc.values()
+        .filter_map(Json::as_str)
+        .flat_map(tokenize)
+}
+
+fn matches_filter(doc: &Map<String, Json>, filter: &[Condition]) -> bool {
+    filter.iter().all(|(field, value)| {
+        let Some(field) = doc.get(*field) else {
+            return false;
+        };
+        match value {
+            FilterValue::U64(value) => field.as_u64() == Some(*value),
+            FilterValue::Bool(value) => field.as_bool() == Some(*value),
+            FilterValue::String(value) => field.as_str() == Some(value.as_str()),
+        }
+    })
+}
+
+#[derive(Default)]
+struct Index {
+    docs: HashMap<u64, Map<String, Json>>,
+    // Term -> (document id -> number of occurrences).
+    // Sorted, so the terms starting with a query word are next to each other.
+    terms: BTreeMap<String, HashMap<u64, u32>>,
+}
+
+impl Index {
+    fn add(&mut self, id: u64, doc: Map<String, Json>) {
+        self.remove(id);
+        for term in doc_terms(&doc) {
+            *self.terms.entry(term).or_default().entry(id).or_default() += 1;
+        }
+        self.docs.insert(id, doc);
+    }
+
+    fn remove(&mut self, id: u64) {
+        let Some(doc) = self.docs.remove(&id) else {
+            return;
+        };
+        for term in doc_terms(&doc) {
+            if let Some(ids) = self.terms.get_mut(&term) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.terms.remove(&term);
+                }
+            }
+        }
+    }
+
+    // Every query word must be a prefix of some word in the document.
+    // Documents with more occurrences of the matched words come first.
+    fn search(&self, query: &str, filter: &[Condition]) -> Vec<u64> {
+        let mut scores: Option<HashMap<u64, u32>> = None;
+        for word in tokenize(query) {
+            let mut word_scores = HashMap::<u64, u32>::new();
+            for (_, ids) in self.terms.range(word.clone() ..).take_while(|(term, _)| term.starts_with(&word)) {
+                for (id, count) in ids {
+                    *word_scores.entry(*id).or_default() += count;
+                }
+            }
+            scores = Some(match scores {
+                None => word_scores,
+                Some(scores) => scores.into_iter()
+                    .filter_map(|(id, score)| word_scores.get(&id).map(|word_score| (id, score + word_score)))
+                    .collect(),
+            });
+        }
+        let scores = scores.unwrap_or_else(|| self.docs.keys().map(|id| (*id, 0)).collect());
+
+        let mut hits = scores.into_iter()
+            .filter(|(id, _)| matches_filter(&self.docs[id], filter))
+            .collect::<Vec<_>>();
+        hits.sort_by(|(a_id, a_score), (b_id, b_score)| b_score.cmp(a_score).then(a_id.cmp(b_id)));
+        hits.into_iter().take(SEARCH_LIMIT).map(|(id, _)| id).collect()
+    }
+}
+
+#[derive(bincode::Encode, bincode::Decode)]
+enum LogEntry {
+    // The document serialized as JSON.
+    Add { id: u64, doc: String },
+    Delete { id: u64 },
+}
+
+fn encode_log_entry(entry: &LogEntry) -> io::Result<Vec<u8>> {
+    bincode::encode_to_vec(entry, bincode::config::standard())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
+
+// The log isn't compacted while it's shorter than this.
+const COMPACT_MIN_LOG_LEN: usize = 1000;
+
+// An index together with its log file. Every change is appended to the log, which is replayed on open.
+struct Stored {
+    index: Index,
+    path: PathBuf,
+    log: File,
+    // Number of entries in the log.
+    log_len: usize,
+    // Entries appended while a compaction writes the new log, they are copied to it when it's finished.
+    // `None` when not compacting.
+    pending: Option<Vec<u8>>,
+    // Counts started compactions, so one that was overtaken by `clear` isn't finished.
+    compactions: u64,
+}
+
+// A compaction started under the lock, see `Stored::start_compaction`.
+struct Compaction {
+    number: u64,
+    // Length of the log when it started.
+    log_len: usize,
+    tmp_path: PathBuf,
+    docs: HashMap<u64, Map<String, Json>>,
+}
+
+impl Compaction {
+    // Writes the documents to a new log. Runs on the blocking thread pool, without the lock.
+    fn write(&self) -> io::Result<()> {
+        let mut tmp = BufWriter::new(File::create(&self.tmp_path)?);
+        for (id, doc) in &self.docs {
+            let doc = serde_json::to_string(doc)?;
+            tmp.write_all(&encode_log_entry(&LogEntry::Add { id: *id, doc })?)?;
+        }
+        tmp.into_inner().map_err(|e| e.into_error())?.sync_all()
+    }
+}
+
+impl Stored {
+    // Only a torn entry at the end of the log, left by a crash while appending, is dropped.
+    // Any other undecodable entry or document is an error.
+    fn open(path: PathBuf) -> Result<Self, Error> {
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => Err(Error::Io(e))?,
+        };
+
+        let mut index = Index::default();
+        let mut offset = 0;
+        let mut log_len = 0;
+        while offset < bytes.len() {
+            let (entry, len) = match bincode::decode_from_slice::<LogEntry, _>(&bytes[offset ..], bincode::config::standard()) {
+                Ok(decoded) => decoded,
+                Err(bincode::error::DecodeError::UnexpectedEnd { .. }) => break,
+                Err(e) => Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData, e.to_string())))?,
+            };
+            match entry {
+                LogEntry::Add { id, doc } => index.add(id, serde_json::from_str(&doc).map_err(Error::Json)?),
+                LogEntry::Delete { id } => index.remove(id),
+            }
+            offset += len;
+            log_len += 1;
+        }
+
+        let log = File::options().create(true).append(true).open(&path).map_err(Error::Io)?;
+        if offset < bytes.len() {
+            log.set_len(offset as u64).map_err(Error::Io)?;
+            log.sync_all().map_err(Error::Io)?;
+        }
+        Ok(Self { index, path, log, log_len, pending: None, compactions: 0 })
+    }
+
+    // The entries are synced to disk before returning.
+    fn append(&mut self, entries: &[LogEntry]) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        for entry in entries {
+            bytes.extend(encode_log_entry(entry)?);
+        }
+        self.log.write_all(&bytes)?;
+        self.log.sync_data()?;
+        if let Some(pending) = &mut self.pending {
+            pending.extend(bytes);
+        }
+        self.log_len += entries.len();
+        Ok(())
+    }
+
+    // Returns a compaction if the log grew to more than twice the number of documents and none is running.
+    fn start_compaction(&mut self) -> Option<Compaction> {
+        if self.pending.is_some() || self.log_len <= COMPACT_MIN_LOG_LEN || self.log_len <= 2 * self.index.docs.len() {
+            return None;
+        }
+        self.compactions += 1;
+        self.pending = Some(Vec::new());
+        Some(Compaction {
+            number: self.compactions,
+            log_len: self.log_len,
+            tmp_path: self.path.with_extension(format!("{}.tmp", self.compactions)),
+            docs: self.index.docs.clone(),
+        })
+    }
+
+    // Copies the entries appended since the compaction started to the new log and replaces the log with it.
+    fn finish_compaction(&mut self, compaction: Compaction) -> io::Result<()> {
+        if compaction.number != self.compactions || self.pending.is_none() {
+            return fs::remove_file(&compaction.tmp_path);
+        }
+        let pending = self.pending.take().unwrap_or_default();
+        let mut tmp = File::options().append(true).open(&compaction.tmp_path)?;
+        tmp.write_all(&pending)?;
+        tmp.sync_all()?;
+        fs::rename(&compaction.tmp_path, &self.path)?;
+        self.log = File::options().append(true).open(&self.path)?;
+        self.log_len = compaction.docs.len() + (self.log_len - compaction.log_len);
+        Ok(())
+    }
+
+    fn add(&mut self, docs: Vec<(u64, Map<String, Json>)>) -> Result<(), Error> {
+        let entries = docs.iter()
+            .map(|(id, doc)| serde_json::to_string(doc).map(|doc| LogEntry::Add { id: *id, doc }))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::Json)?;
+        self.append(&entries).map_err(Error::Io)?;
+        for (id, doc) in docs {
+            self.index.add(id, doc);
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, id: u64) -> Result<(), Error> {
+        if self.index.docs.contains_key(&id) {
+            self.append(&[LogEntry::Delete { id }]).map_err(Error::Io)?;
+            self.index.remove(id);
+        }
+        Ok(())
+    }
+
+    // Overtakes a running compaction.
+    fn clear(&mut self) -> Result<(), Error> {
+        self.log.set_len(0).map_err(Error::Io)?;
+        self.log.sync_all().map_err(Error::Io)?;
+        self.log_len = 0;
+        self.pending = None;
+        self.index = Index::default();
+        Ok(())
+    }
+}
+
+// In-process search engine, so the database can run without meilisearch.
+// Each index is kept in memory and persisted as a log file `{INDEX}.log` in its directory.
+pub struct Value {
+    dir: PathBuf,
+    indexes: RwLock<HashMap<String, Stored>>,
+}
+
+impl Value {
+    pub fn open(dir: PathBuf) -> Result<Self, Error> {
+        fs::create_dir_all(&dir).map_err(Error::Io)?;
+        let mut indexes = HashMap::new();
+        for entry in fs::read_dir(&dir).map_err(Error::Io)? {
+            let path = entry.map_err(Error::Io)?.path();
+            if path.extension().is_some_and(|ext| ext == "log") {
+                if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                    indexes.insert(name.to_string(), Stored::open(path.clone())?);
+                }
+            }
+        }
+        Ok(Self {
+            dir,
+            indexes: RwLock::new(indexes),
+        })
+    }
+
+    // The documents are written to the new log on the blocking thread pool, without holding the lock.
+    // The write that started the compaction waits for it, so the database stays locked meanwhile.
+    async fn compact(&self, index: &str, compaction: Compaction) -> Result<(), Error> {
+        let number = compaction.number;
+        let tmp_path = compaction.tmp_path.clone();
+        let written = tokio::task::spawn_blocking(move || compaction.write().map(|_| compaction))
+            .await
+            .map_err(io::Error::other)
+            .and_then(|written| written);
+        let mut indexes = self.indexes.write().unwrap();
+        let Some(stored) = indexes.get_mut(index) else {
+            return Ok(());
+        };
+        match written {
+            Ok(compaction) => stored.finish_compaction(compaction).map_err(Error::Io),
+            Err(e) => {
+                if number == stored.compactions {
+                    stored.pending = None;
+                }
+                let _ = fs::remove_file(&tmp_path);
+                Err(Error::Io(e))
+            },
+        }
+    }
+}
+
+fn to_doc<T: Serialize>(doc: &T) -> Result<(u64, Map<String, Json>), Error> {
+    match serde_json::to_value(doc).map_err(Error::Json)? {
+        Json::Object(doc) => {
+            let id = doc.get("id").and_then(Json::as_u64).ok_or(Error::InvalidDocument)?;
+            Ok((id, doc))
+        },
+        _ => Err(Error::InvalidDocument),
+    }
+}
+
+impl SearchBackend for Value {
+    async fn index<T: Serialize + Send + Sync>(&self, index: &str, docs: &[T]) -> Result<(), Error> {
+        let docs = docs.iter().map(to_doc).collect::<Result<Vec<_>, _>>()?;
+        let compaction = {
+            let mut indexes = self.indexes.write().unwrap();
+            if !indexes.contains_key(index) {
+                let stored = Stored::open(self.dir.join(format!("{index}.log")))?;
+                indexes.insert(index.to_string(), stored);
+            }
+            let stored = indexes.get_mut(index).unwrap();
+            stored.add(docs)?;
+            stored.start_compaction()
+        };
+        match compaction {
+            Some(compaction) => self.compact(index, compaction).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn delete(&self, index: &str, id: u64) -> Result<(), Error> {
+        let compaction = match self.indexes.write().unwrap().get_mut(index) {
+            Some(stored) => {
+                stored.remove(id)?;
+                stored.start_compaction()
+            },
+            None => None,
+        };
+        match compaction {
+            Some(compaction) => self.compact(index, compaction).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn delete_all(&self, index: &str) -> Result<(), Error> {
+        if let Some(stored) = self.indexes.write().unwrap().get_mut(index) {
+            stored.clear()?;
+        }
+        Ok(())
+    }
+
+    async fn search(&self, index: &str, query: &str, filter: &[Condition]) -> Result<Vec<u64>, Error> {
+        Ok(self.indexes.read().unwrap()
+            .get(index)
+            .map(|stored| stored.index.search(query, filter))
+            .unwrap_or_default())
+    }
+
+    // Every field can be filtered on.
+    async fn set_filterable(&self, _index: &str, _fields: &[&str]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn ids(&self, index: &str) -> Result<Vec<u64>, Error> {
+        Ok(self.indexes.read().unwrap()
+            .get(index)
+            .map(|stored| stored.index.docs.keys().copied().collect())
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use serde_json::{json, Map, Value as Json};
+    use crate::{search::{Error, FilterValue}, tests::dir};
+    use super::{tokenize, Index, LogEntry, Stored};
+
+    fn doc(id: u64, title: &str, author: &str) -> Map<String, Json> {
+        match json!({ "id": id, "title": title, "author": author }) {
+            Json::Object(doc) => doc,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn tokenize_splits_and_lowercases() {
+        let words = tokenize("Hello, World! paper_minecraft v1.2 ÄÖ").collect::<Vec<_>>();
+        assert_eq!(words, ["hello", "world", "paper", "minecraft", "v1", "2", "äö"]);
+    }
+
+    #[test]
+    fn search_matches_prefixes_of_every_word() {
+        let mut index = Index::default();
+        index.add(1, doc(1, "Paper Minecraft", "griffpatch"));
+        index.add(2, doc(2, "Minecraft platformer", "kaj"));
+        index.add(3, doc(3, "Minecraft minecraft", "kaj"));
+
+        // More occurrences first, then by id.
+        assert_eq!(index.search("mine", &[]), [3, 1, 2]);
+        assert_eq!(index.search("MINE pap", &[]), [1]);
+        assert_eq!(index.search("mine", &[("author", FilterValue::String("kaj".into()))]), [3, 2]);
+        assert!(index.search("craft", &[]).is_empty());
+
+        index.remove(3);
+        index.remove(2);
+        assert_eq!(index.search("minecraft", &[]), [1]);
+        assert!(!index.terms.contains_key("platformer"));
+    }
+
+    #[test]
+    fn log_is_replayed_and_torn_tail_dropped() {
+        let dir = dir("embedded_log");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("projects.log");
+        let mut stored = Stored::open(path.clone()).unwrap();
+        stored.add(vec![(1, doc(1, "Paper Minecraft", "griffpatch")), (2, doc(2, "Platformer", "kaj"))]).unwrap();
+        stored.remove(2).unwrap();
+        drop(stored);
+        // Crashed halfway through appending an entry.
+        let len = fs::metadata(&path).unwrap().len();
+        let entry = super::encode_log_entry(&LogEntry::Delete { id: 1 }).unwrap();
+        fs::write(&path, [fs::read(&path).unwrap(), entry[.. entry.len() - 1].to_vec()].concat()).unwrap();
+
+        let stored = Stored::open(path.clone()).unwrap();
+        assert_eq!(stored.log_len, 3);
+        assert_eq!(stored.index.search("paper", &[]), [1]);
+        assert!(stored.index.search("platformer", &[]).is_empty());
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    }
+
+    #[test]
+    fn open_reports_invalid_documents() {
+        let dir = dir("embedded_invalid");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("projects.log");
+        let entry = super::encode_log_entry(&LogEntry::Add { id: 1, doc: "{".into() }).unwrap();
+        fs::write(&path, entry).unwrap();
+        assert!(matches!(Stored::open(path), Err(Error::Json(_))));
+    }
+
+    #[test]
+    fn compaction_keeps_entries_appended_meanwhile() {
+        let dir = dir("embedded_compact");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("projects.log");
+        let mut stored = Stored::open(path.clone()).unwrap();
+        stored.add(vec![(1, doc(1, "Paper Minecraft", "griffpatch")), (2, doc(2, "Platformer", "kaj"))]).unwrap();
+        stored.log_len = 10_000;
+
+        let compaction = stored.start_compaction().unwrap();
+        assert!(stored.start_compaction().is_none());
+        compaction.write().unwrap();
+        stored.remove(1).unwrap();
+        stored.add(vec![(3, doc(3, "Scratch", "kaj"))]).unwrap();
+        stored.finish_compaction(compaction).unwrap();
+        assert_eq!(stored.log_len, 4);
+        drop(stored);
+
+        let stored = Stored::open(path).unwrap();
+        assert_eq!(stored.log_len, 4);
+        let mut ids = stored.index.docs.keys().copied().collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, [2, 3]);
+    }
+}
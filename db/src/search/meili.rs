@@ -0,0 +1,108 @@
+use meilisearch_sdk::{client::Client as MeiliClient, documents::DocumentsQuery, errors::ErrorCode};
+use serde::Serialize;
+use super::{Condition, Error, FilterValue, SearchBackend};
+
+#[derive(serde::Deserialize)]
+struct Hit {
+    id: u64,
+}
+
+fn to_meili_filter(filter: &[Condition]) -> Option<String> {
+    if filter.is_empty() {
+        return None;
+    }
+    let conditions = filter.iter()
+        .map(|(field, value)| match value {
+            FilterValue::U64(value) => format!("{field} = {value}"),
+            FilterValue::Bool(value) => format!("{field} = {value}"),
+            FilterValue::String(value) => format!("{field} = \"{}\"", value.replace('"', "\\\"")),
+        })
+        .collect::<Vec<_>>();
+    Some(conditions.join(" AND "))
+}
+
+// Number of documents fetched per request when listing ids.
+const IDS_PAGE_LEN: usize = 1000;
+
+pub struct Value {
+    client: MeiliClient,
+}
+
+impl Value {
+    pub fn new(host: impl Into<String>, key: impl Into<String>) -> Result<Self, Error> {
+        Ok(Self {
+            client: MeiliClient::new(host, Some(key)).map_err(Error::Meili)?,
+        })
+    }
+}
+
+impl SearchBackend for Value {
+    async fn index<T: Serialize + Send + Sync>(&self, index: &str, docs: &[T]) -> Result<(), Error> {
+        self.client.index(index)
+            .add_documents(docs, None)
+            .await
+            .map_err(Error::Meili)?;
+        Ok(())
+    }
+
+    async fn delete(&self, index: &str, id: u64) -> Result<(), Error> {
+        self.client.index(index)
+            .delete_document(id)
+            .await
+            .map_err(Error::Meili)?;
+        Ok(())
+    }
+
+    async fn delete_all(&self, index: &str) -> Result<(), Error> {
+        self.client.index(index)
+            .delete_all_documents()
+            .await
+            .map_err(Error::Meili)?;
+        Ok(())
+    }
+
+    async fn search(&self, index: &str, query: &str, filter: &[Condition]) -> Result<Vec<u64>, Error> {
+        let index = self.client.index(index);
+        let meili_filter = to_meili_filter(filter);
+        let mut search = index.search();
+        search.with_query(query);
+        if let Some(meili_filter) = &meili_filter {
+            search.with_filter(meili_filter);
+        }
+        let res = search.execute::<Hit>().await.map_err(Error::Meili)?;
+        Ok(res.hits.into_iter().map(|hit| hit.result.id).collect())
+    }
+
+    async fn set_filterable(&self, index: &str, fields: &[&str]) -> Result<(), Error> {
+        self.client.index(index)
+            .set_filterable_attributes(fields)
+            .await
+            .map_err(Error::Meili)?;
+        Ok(())
+    }
+
+    async fn ids(&self, index: &str) -> Result<Vec<u64>, Error> {
+        let index = self.client.index(index);
+        let mut ids = Vec::new();
+        loop {
+            let res = DocumentsQuery::new(&index)
+                .with_fields(["id"])
+                .with_offset(ids.len())
+                .with_limit(IDS_PAGE_LEN)
+                .execute::<Hit>()
+                .await;
+            let page = match res {
+                Ok(page) => page,
+                // Nothing was ever indexed.
+                Err(meilisearch_sdk::errors::Error::Meilisearch(e)) if e.error_code == ErrorCode::IndexNotFound => break,
+                Err(e) => Err(Error::Meili(e))?,
+            };
+            let len = page.results.len();
+            ids.extend(page.results.into_iter().map(|hit| hit.id));
+            if len < IDS_PAGE_LEN {
+                break;
+            }
+        }
+        Ok(ids)
+    }
+}
@@ -0,0 +1,319 @@
+use std::{fs::{self, File}, io::{self, Read, Write}, path::{Component, Path, PathBuf}};
+use ring::digest;
+use tokio::sync::OwnedRwLockReadGuard;
+use crate::{auth, manifest, InternalError, FORMAT_VERSION};
+
+// Written last, so a backup without it is incomplete.
+// Named apart from the database manifest, so a backup folder can't be mistaken for a database folder.
+pub const MANIFEST_FILE: &str = "backup.json";
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct FileEntry {
+    // Path relative to the backup directory, with `/` separators.
+    pub name: String,
+    pub len: u64,
+    // Hex encoded SHA-256 of the contents.
+    pub checksum: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct Manifest {
+    // `FORMAT_VERSION` of the database the backup was made from.
+    pub format_version: u32,
+    // Unix timestamp (seconds).
+    pub created: i64,
+    pub files: Vec<FileEntry>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // The destination exists and isn't an empty directory.
+    DestNotEmpty,
+    // The journal holds an unfinished write, the database must be recovered first.
+    NeedsRecovery,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum BackupError {
+    Internal,
+    Auth(auth::EnsureAuthError),
+    DestNotEmpty,
+    NeedsRecovery,
+}
+
+#[derive(Debug)]
+pub enum RestoreError {
+    Io(io::Error),
+    // The manifest is missing, so the backup wasn't finished.
+    NoManifest,
+    InvalidManifest(serde_json::Error),
+    // The backup doesn't list the manifest of the database it was made from.
+    NoDatabaseManifest,
+    FormatVersion { found: u32 },
+    // The destination exists and isn't an empty directory.
+    DestNotEmpty,
+    // A file name that isn't a relative path inside the backup, like `../config.toml`.
+    UnsafeName(String),
+    MissingFile(String),
+    LenMismatch { name: String, expected: u64, found: u64 },
+    ChecksumMismatch(String),
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// Reads `src` to the end, writing it to `dest` if given. Returns the length and the checksum.
+fn read_checked(src: &Path, mut dest: Option<&mut File>) -> io::Result<(u64, String)> {
+    let mut file = File::open(src)?;
+    let mut context = digest::Context::new(&digest::SHA256);
+    let mut buf = vec![0; 1 << 16];
+    let mut len = 0;
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buf[.. read]);
+        if let Some(dest) = dest.as_mut() {
+            dest.write_all(&buf[.. read])?;
+        }
+        len += read as u64;
+    }
+    Ok((len, to_hex(context.finish().as_ref())))
+}
+
+// Syncs `src` to disk, then copies it to `dest` and syncs the copy.
+// Mapped storages must be flushed first, see `Value::flush`.
+fn copy_synced(src: &Path, dest: &Path) -> io::Result<(u64, String)> {
+    File::options().read(true).write(true).open(src)?.sync_all()?;
+    let mut file = File::create(dest)?;
+    let out = read_checked(src, Some(&mut file))?;
+    file.sync_all()?;
+    Ok(out)
+}
+
+// Returns false if `dir` has anything in it, creates it if it doesn't exist.
+fn ensure_empty_dir(dir: &Path) -> io::Result<bool> {
+    match fs::read_dir(dir) {
+        Ok(mut entries) => Ok(entries.next().is_none()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => fs::create_dir_all(dir).map(|_| true),
+        Err(e) => Err(e),
+    }
+}
+
+// Names in a manifest must stay inside the directory they are joined to.
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty() && Path::new(name).components().all(|component| matches!(component, Component::Normal(_)))
+}
+
+// The folder a backup is copied into before it's renamed to `dest`, next to it so the rename doesn't cross file systems.
+fn restoring_dir(dest: &Path) -> io::Result<PathBuf> {
+    let name = dest.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the destination has no file name"))?;
+    let mut name = name.to_os_string();
+    name.push(".restoring");
+    Ok(dest.with_file_name(name))
+}
+
+// Copies the checked files into `tmp`.
+fn copy_backup(src: &Path, tmp: &Path, manifest: &Manifest) -> Result<(), RestoreError> {
+    for entry in &manifest.files {
+        let dest_path = tmp.join(&entry.name);
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent).map_err(RestoreError::Io)?;
+        }
+        let (_, checksum) = copy_synced(&src.join(&entry.name), &dest_path).map_err(RestoreError::Io)?;
+        // The backup could have changed since it was checked.
+        if checksum != entry.checksum {
+            Err(RestoreError::ChecksumMismatch(entry.name.clone()))?
+        }
+    }
+    Ok(())
+}
+
+fn write_manifest(dir: &Path, manifest: &Manifest) -> io::Result<()> {
+    let tmp_path = dir.join(MANIFEST_FILE).with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(&serde_json::to_vec_pretty(manifest)?)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, dir.join(MANIFEST_FILE))
+}
+
+// Checks every file of the backup in `src` against its manifest, then copies them into `dest`,
+// along with the manifest of the database the backup was made from.
+// The files are copied into a folder next to `dest` which is renamed to it once complete,
+// so `dest` is left as it was unless the whole backup was restored.
+pub fn restore(src: &Path, dest: &Path) -> Result<Manifest, RestoreError> {
+    let manifest = match fs::read(src.join(MANIFEST_FILE)) {
+        Ok(bytes) => serde_json::from_slice::<Manifest>(&bytes).map_err(RestoreError::InvalidManifest)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(RestoreError::NoManifest)?,
+        Err(e) => Err(RestoreError::Io(e))?,
+    };
+    if manifest.format_version != FORMAT_VERSION {
+        Err(RestoreError::FormatVersion { found: manifest.format_version })?
+    }
+    if let Some(entry) = manifest.files.iter().find(|entry| !is_safe_name(&entry.name)) {
+        Err(RestoreError::UnsafeName(entry.name.clone()))?
+    }
+    if !manifest.files.iter().any(|entry| entry.name == manifest::MANIFEST_FILE) {
+        Err(RestoreError::NoDatabaseManifest)?
+    }
+
+    for entry in &manifest.files {
+        let (len, checksum) = match read_checked(&src.join(&entry.name), None) {
+            Ok(out) => out,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(RestoreError::MissingFile(entry.name.clone()))?,
+            Err(e) => Err(RestoreError::Io(e))?,
+        };
+        if len != entry.len {
+            Err(RestoreError::LenMismatch { name: entry.name.clone(), expected: entry.len, found: len })?
+        }
+        if checksum != entry.checksum {
+            Err(RestoreError::ChecksumMismatch(entry.name.clone()))?
+        }
+    }
+
+    let dest_exists = match fs::read_dir(dest) {
+        Ok(mut entries) if entries.next().is_none() => true,
+        Ok(_) => Err(RestoreError::DestNotEmpty)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+        Err(e) => Err(RestoreError::Io(e))?,
+    };
+    let tmp = restoring_dir(dest).map_err(RestoreError::Io)?;
+    // Left behind by an earlier restore that crashed.
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp).map_err(RestoreError::Io)?;
+    }
+    fs::create_dir_all(&tmp).map_err(RestoreError::Io)?;
+    let restored = copy_backup(src, &tmp, &manifest).and_then(|_| {
+        if dest_exists {
+            fs::remove_dir(dest).map_err(RestoreError::Io)?;
+        }
+        fs::rename(&tmp, dest).map_err(RestoreError::Io)
+    });
+    if let Err(e) = restored {
+        let _ = fs::remove_dir_all(&tmp);
+        Err(e)?
+    }
+    Ok(manifest)
+}
+
+impl crate::Value {
+    // Copies the database manifest, every storage and the embedded search index into `dest`, then writes the backup manifest.
+    // Writes must not happen meanwhile, which the borrow guarantees when the database is behind a `RwLock`.
+    pub fn snapshot(&self, dest: &Path) -> Result<Manifest, Error> {
+        if self.needs_recovery {
+            Err(Error::NeedsRecovery)?
+        }
+        if !ensure_empty_dir(dest).map_err(Error::Io)? {
+            Err(Error::DestNotEmpty)?
+        }
+        self.flush().map_err(Error::Io)?;
+
+        let mut names = vec![manifest::MANIFEST_FILE.to_string()];
+        names.extend(self.files.iter().map(|name| name.to_string()));
+        let search_dir = self.dir.join("search");
+        if search_dir.is_dir() {
+            fs::create_dir_all(dest.join("search")).map_err(Error::Io)?;
+            for entry in fs::read_dir(&search_dir).map_err(Error::Io)? {
+                let path = entry.map_err(Error::Io)?.path();
+                if path.extension().is_some_and(|ext| ext == "log") {
+                    if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                        names.push(format!("search/{name}"));
+                    }
+                }
+            }
+        }
+
+        let mut files = Vec::with_capacity(names.len());
+        for name in names {
+            let (len, checksum) = copy_synced(&self.dir.join(&name), &dest.join(&name)).map_err(Error::Io)?;
+            files.push(FileEntry { name, len, checksum });
+        }
+
+        let manifest = Manifest {
+            format_version: FORMAT_VERSION,
+            created: chrono::Utc::now().timestamp(),
+            files,
+        };
+        write_manifest(dest, &manifest).map_err(Error::Io)?;
+        Ok(manifest)
+    }
+
+    // `snapshot` for the admin endpoint, the key must allow every operation.
+    // The copy runs on the blocking thread pool, holding the read guard until it's done so writes wait for it.
+    pub async fn backup(db: OwnedRwLockReadGuard<Self>, auth_key: Option<&auth::Key>, dest: PathBuf) -> Result<Manifest, BackupError> {
+        for op in [auth::Op::Read, auth::Op::Write, auth::Op::Remove] {
+            db.ensure_auth(op, auth_key).map_err(BackupError::Auth)?;
+        }
+        let (db, snapshot) = tokio::task::spawn_blocking(move || {
+            let snapshot = db.snapshot(&dest);
+            (db, snapshot)
+        }).await.map_err(|_| BackupError::Internal)?;
+        match snapshot {
+            Ok(manifest) => Ok(manifest),
+            Err(Error::Io(e)) => {
+                db.send_error(InternalError::Snapshot(e)).await;
+                Err(BackupError::Internal)
+            },
+            Err(Error::DestNotEmpty) => Err(BackupError::DestNotEmpty),
+            Err(Error::NeedsRecovery) => Err(BackupError::NeedsRecovery),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::{Path, PathBuf}};
+    use crate::{manifest, tests::{dir, open, user}};
+    use super::{restore, Manifest, RestoreError, MANIFEST_FILE};
+
+    // A folder with a database holding one user in `db` and its backup in `backup`.
+    async fn backed_up(name: &str) -> PathBuf {
+        let dir = dir(name);
+        let mut db = open(&dir.join("db")).await;
+        db.add_user(None, user("griffpatch", 1)).await.unwrap();
+        db.snapshot(&dir.join("backup")).unwrap();
+        dir
+    }
+
+    fn read_manifest(src: &Path) -> Manifest {
+        serde_json::from_slice(&fs::read(src.join(MANIFEST_FILE)).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn restore_copies_the_backup() {
+        let dir = backed_up("snapshot_restore").await;
+        restore(&dir.join("backup"), &dir.join("restored")).unwrap();
+        let db = open(&dir.join("restored")).await;
+        assert_eq!(db.user_by_id(None, &1).unwrap().name, "griffpatch".parse().unwrap());
+        // The database manifest is the one of the backed up database, not a new one.
+        let restored = manifest::read(&dir.join("restored")).unwrap().unwrap();
+        let original = manifest::read(&dir.join("db")).unwrap().unwrap();
+        assert_eq!(restored.created, original.created);
+        assert_eq!(restored.storages, original.storages);
+    }
+
+    #[tokio::test]
+    async fn restore_rejects_tampered_backup() {
+        let dir = backed_up("snapshot_tampered").await;
+        let src = dir.join("backup");
+        let dest = dir.join("restored");
+        let manifest = read_manifest(&src);
+
+        let changed = manifest.files.iter().find(|entry| entry.len > 0).unwrap();
+        let mut bytes = fs::read(src.join(&changed.name)).unwrap();
+        bytes[0] ^= 1;
+        fs::write(src.join(&changed.name), bytes).unwrap();
+        assert!(matches!(restore(&src, &dest), Err(RestoreError::ChecksumMismatch(name)) if name == changed.name));
+        assert!(!dest.exists());
+
+        let mut escaping = manifest.clone();
+        escaping.files[0].name = "../db/manifest.json".to_string();
+        fs::write(src.join(MANIFEST_FILE), serde_json::to_vec(&escaping).unwrap()).unwrap();
+        assert!(matches!(restore(&src, &dest), Err(RestoreError::UnsafeName(_))));
+        assert!(!dest.exists());
+    }
+}
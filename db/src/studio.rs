@@ -0,0 +1,508 @@
+use bitflags::bitflags;
+use bindb::storage::IndexedDynamic;
+use crate::{auth, entity::{set_index, unset_index, Entity}, journal, list_limit, search::SearchBackend, BindbErrorKind, BindbErrorOp, InternalError};
+use binbuf::impls::ArbNum;
+use binbuf::impls::dynamic::StringCLL;
+
+bitflags! {
+    pub struct Flags: u8 {
+        const OPEN_TO_ALL = 1;
+        const COMMENTS_ALLOWED = 1 << 1;
+    }
+}
+
+binbuf::dynamic! {
+    pub struct DbRepr {
+        pub flags: u8,
+        pub id: u64,
+        pub host_id: u64,
+        pub created: i64,
+        pub modified: i64,
+        pub title: StringCLL<2>,
+        pub description: StringCLL<2>,
+    }
+    buf! { pub struct DbReprBuf<P>(DbRepr, P); }
+    impl I for DbRepr { type Buf<P> = DbReprBuf<P>; }
+    impl Code for DbRepr {}
+}
+
+impl From<DbRepr> for Value {
+    fn from(value: DbRepr) -> Self {
+        let flags = Flags::from_bits_retain(value.flags);
+        Self {
+            id: value.id,
+            open_to_all: flags.contains(Flags::OPEN_TO_ALL),
+            comments_allowed: flags.contains(Flags::COMMENTS_ALLOWED),
+            host_id: value.host_id,
+            created: value.created,
+            modified: value.modified,
+            title: value.title.into(),
+            description: value.description.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct Value {
+    pub id: u64,
+    pub open_to_all: bool,
+    pub comments_allowed: bool,
+    // Scratch id of the user who owns the studio.
+    pub host_id: u64,
+    pub created: i64,
+    pub modified: i64,
+    pub title: String,
+    pub description: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ToDbReprError {
+    TitleTooLong,
+    DescriptionTooLong,
+}
+
+impl Value {
+    pub fn to_db_repr(self) -> Result<DbRepr, ToDbReprError> {
+        let mut flags = Flags::empty();
+        if self.open_to_all { flags |= Flags::OPEN_TO_ALL; }
+        if self.comments_allowed { flags |= Flags::COMMENTS_ALLOWED; }
+        Ok(DbRepr {
+            flags: flags.bits(),
+            id: self.id,
+            host_id: self.host_id,
+            created: self.created,
+            modified: self.modified,
+            title: StringCLL::try_from_string(self.title).ok_or(ToDbReprError::TitleTooLong)?,
+            description: StringCLL::try_from_string(self.description).ok_or(ToDbReprError::DescriptionTooLong)?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Curator,
+    Manager,
+    Host,
+}
+
+impl Role {
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Curator => 0,
+            Self::Manager => 1,
+            Self::Host => 2,
+        }
+    }
+
+    // `None` for bytes no role is stored as.
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Curator),
+            1 => Some(Self::Manager),
+            2 => Some(Self::Host),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct Member {
+    // Scratch id of the user.
+    pub user_id: u64,
+    pub role: Role,
+}
+
+// Key of a user in the members of a studio, the value is the `Role`.
+binbuf::fixed! {
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct MemberKey {
+        pub studio_id: u64,
+        pub user_id: u64,
+    }
+
+    buf! { pub struct MemberKeyBuf<P>(MemberKey, P); }
+
+    impl I for MemberKey {
+        type Buf<P> = MemberKeyBuf<P>;
+    }
+    impl Code for MemberKey {}
+}
+
+// Key of a project in a studio, the value is the time it was recorded.
+binbuf::fixed! {
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct ProjectKey {
+        pub studio_id: u64,
+        pub project_id: u64,
+    }
+
+    buf! { pub struct ProjectKeyBuf<P>(ProjectKey, P); }
+
+    impl I for ProjectKey {
+        type Buf<P> = ProjectKeyBuf<P>;
+    }
+    impl Code for ProjectKey {}
+}
+
+pub type Members = bindb::storage::BinaryTree<ArbNum<8, u64>, MemberKey, u8>;
+pub type Projects = bindb::storage::BinaryTree<ArbNum<8, u64>, ProjectKey, i64>;
+
+// A page of members or projects of a studio sorted by id, starting right after the `after` id.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct ListQuery {
+    pub after: Option<u64>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct MeiliDoc {
+    pub id: u64,
+    pub host_id: u64,
+    pub title: String,
+    pub description: String,
+}
+
+impl MeiliDoc {
+    pub fn new(id: u64, value: Value) -> Self {
+        Self {
+            id,
+            host_id: value.host_id,
+            title: value.title,
+            description: value.description,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum GetByIdError {
+    Auth(auth::EnsureAuthError),
+    NotFound,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum AddError {
+    Internal,
+    Auth(auth::EnsureAuthError),
+    BadInput(ToDbReprError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum SearchError {
+    Internal,
+    Auth(auth::EnsureAuthError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum RemoveByIdError {
+    Internal,
+    Auth(auth::EnsureAuthError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum WriteListError {
+    Internal,
+    Auth(auth::EnsureAuthError),
+    NotFound,
+}
+
+// A change to the members or projects of a studio, recorded in the journal while it's written.
+// Applying it again is harmless, so an interrupted change is finished by recovery.
+#[derive(Clone, Debug, bincode::Encode, bincode::Decode)]
+pub enum ListWrite {
+    // Pairs of a user id and a stored `Role`.
+    AddMembers { studio_id: u64, members: Vec<(u64, u8)> },
+    RemoveMember { studio_id: u64, user_id: u64 },
+    AddProjects { studio_id: u64, project_ids: Vec<u64>, added: i64 },
+    RemoveProject { studio_id: u64, project_id: u64 },
+}
+
+impl ListWrite {
+    pub fn op(&self) -> BindbErrorOp {
+        match self {
+            Self::AddMembers { .. } | Self::RemoveMember { .. } => BindbErrorOp::WriteStudioMembers,
+            Self::AddProjects { .. } | Self::RemoveProject { .. } => BindbErrorOp::WriteStudioProjects,
+        }
+    }
+}
+
+impl Entity for DbRepr {
+    const ADD_OP: BindbErrorOp = BindbErrorOp::AddStudio;
+    const REPLACE_OP: BindbErrorOp = BindbErrorOp::UpsertStudio;
+    const REMOVE_OP: BindbErrorOp = BindbErrorOp::RemoveStudioById;
+    const SEARCH_INDEX: Option<&'static str> = Some("studios");
+    type Doc = MeiliDoc;
+
+    fn storage(db: &crate::Value) -> &IndexedDynamic<Self> {
+        &db.studios
+    }
+
+    fn storage_mut(db: &mut crate::Value) -> &mut IndexedDynamic<Self> {
+        &mut db.studios
+    }
+
+    fn journal_entry(id: u64) -> journal::Entry {
+        journal::Entry::Studio(id)
+    }
+
+    fn add_intent(id: u64) -> journal::Intent {
+        journal::Intent::AddStudio { id }
+    }
+
+    fn replace_intent(old_id: u64, id: u64) -> journal::Intent {
+        journal::Intent::ReplaceStudio { old_id, id }
+    }
+
+    fn remove_intent(id: u64) -> journal::Intent {
+        journal::Intent::RemoveStudio { id }
+    }
+
+    fn doc(id: u64, data: Self) -> MeiliDoc {
+        MeiliDoc::new(id, data.into())
+    }
+
+    unsafe fn set_indexes(db: &mut crate::Value, data: &Self, id: u64) -> Result<(), BindbErrorKind> {
+        set_index(&mut db.studios_id_index, &data.id, id)
+    }
+
+    unsafe fn unset_indexes(db: &mut crate::Value, data: &Self, id: u64) -> Result<(), BindbErrorKind> {
+        unset_index(&mut db.studios_id_index, &data.id, id)
+    }
+
+    // Members and projects of the studio are removed with it.
+    unsafe fn remove_owned(db: &mut crate::Value, data: &Self) -> Result<(), BindbErrorKind> {
+        db.clear_studio_lists(data.id)
+    }
+}
+
+impl super::Value {
+    // Removes every member and project of the studio.
+    unsafe fn clear_studio_lists(&mut self, studio_id: u64) -> Result<(), BindbErrorKind> {
+        let member_keys = iter_from!(self.studios_members, MemberKey { studio_id, user_id: 0 })
+            .map(|(key, _)| key)
+            .take_while(|key| key.studio_id == studio_id)
+            .collect::<Vec<_>>();
+        for key in member_keys {
+            if let Ok(searched) = self.studios_members.search(&key).find() {
+                self.studios_members.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+            }
+        }
+
+        let project_keys = iter_from!(self.studios_projects, ProjectKey { studio_id, project_id: 0 })
+            .map(|(key, _)| key)
+            .take_while(|key| key.studio_id == studio_id)
+            .collect::<Vec<_>>();
+        for key in project_keys {
+            if let Ok(searched) = self.studios_projects.search(&key).find() {
+                self.studios_projects.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Every step is idempotent. Nothing is written if the studio was removed since,
+    // its lists were cleared along with it.
+    pub(crate) unsafe fn apply_list_write(&mut self, write: &ListWrite) -> Result<(), BindbErrorKind> {
+        match write {
+            &ListWrite::AddMembers { studio_id, ref members } => {
+                if self.studios_id_index.get(&studio_id).is_none() {
+                    return Ok(());
+                }
+                for &(user_id, role) in members {
+                    let key = MemberKey { studio_id, user_id };
+                    match self.studios_members.search(&key).find() {
+                        Ok(searched) => {
+                            self.studios_members.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+                            self.studios_members.add(&key, &role).map_err(BindbErrorKind::BinaryTreeAdd)?;
+                        },
+                        Err(searched) => {
+                            self.studios_members.add_searched(&searched, &key, &role).map_err(BindbErrorKind::BinaryTreeAdd)?;
+                        }
+                    }
+                }
+            },
+            &ListWrite::RemoveMember { studio_id, user_id } => {
+                if let Ok(searched) = self.studios_members.search(&MemberKey { studio_id, user_id }).find() {
+                    self.studios_members.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+                }
+            },
+            &ListWrite::AddProjects { studio_id, ref project_ids, added } => {
+                if self.studios_id_index.get(&studio_id).is_none() {
+                    return Ok(());
+                }
+                for &project_id in project_ids {
+                    let key = ProjectKey { studio_id, project_id };
+                    if let Err(searched) = self.studios_projects.search(&key).find() {
+                        self.studios_projects.add_searched(&searched, &key, &added).map_err(BindbErrorKind::BinaryTreeAdd)?;
+                    }
+                }
+            },
+            &ListWrite::RemoveProject { studio_id, project_id } => {
+                if let Ok(searched) = self.studios_projects.search(&ProjectKey { studio_id, project_id }).find() {
+                    self.studios_projects.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // If applying fails, the intent stays in the journal and the write is finished by recovery.
+    async fn write_list(&mut self, write: ListWrite) -> Result<(), WriteListError> {
+        let op = write.op();
+        if let Err(e) = self.journal.write(&journal::Intent::WriteStudioList(write.clone())) {
+            self.send_error(InternalError::Journal(e)).await;
+            Err(WriteListError::Internal)?
+        }
+        if let Err(e) = unsafe { self.apply_list_write(&write) } {
+            self.send_bindb_error(op, e).await;
+            self.needs_recovery = true;
+            Err(WriteListError::Internal)?
+        }
+        self.finish_intent(op, None).await;
+        Ok(())
+    }
+
+    pub fn studio_by_id(&self, auth_key: Option<&auth::Key>, id: &u64) -> Result<Value, GetByIdError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(GetByIdError::Auth)?;
+        self.studios_id_index.get(id)
+            .map(|id| {
+                self.studios.get(id.get()).into()
+            })
+            .ok_or(GetByIdError::NotFound)
+    }
+
+    // Returns true if already exists.
+    pub async fn add_studio(&mut self, auth_key: Option<&auth::Key>, value: Value) -> Result<bool, AddError> {
+        self.ensure_auth(auth::Op::Write, auth_key).map_err(AddError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| AddError::Internal)?;
+        if self.studios_id_index.get(&value.id).is_some() {
+            return Ok(true);
+        }
+
+        let db_repr = value.to_db_repr().map_err(AddError::BadInput)?;
+        self.write_add(&db_repr).await.map_err(|_| AddError::Internal)?;
+        Ok(false)
+    }
+
+    // Inserts the studio, or overwrites the existing studio with the same id.
+    // Members and projects of the studio are kept.
+    // Returns true if already exists.
+    pub async fn upsert_studio(&mut self, auth_key: Option<&auth::Key>, value: Value) -> Result<bool, AddError> {
+        self.ensure_auth(auth::Op::Write, auth_key).map_err(AddError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| AddError::Internal)?;
+        let old_id = match self.studios_id_index.get(&value.id) {
+            Some(old_id) => old_id.get(),
+            None => return self.add_studio(auth_key, value).await,
+        };
+
+        let db_repr = value.to_db_repr().map_err(AddError::BadInput)?;
+        self.write_replace(old_id, &db_repr).await.map_err(|_| AddError::Internal)?;
+        Ok(true)
+    }
+
+    pub async fn search_studios(&self, auth_key: Option<&auth::Key>, query: &str) -> Result<Vec<Value>, SearchError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(SearchError::Auth)?;
+        let ids = match self.search.search("studios", query, &[]).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                self.send_search_error(e).await;
+                Err(SearchError::Internal)?
+            }
+        };
+
+        let mut hits = Vec::with_capacity(ids.len());
+        for id in ids {
+            hits.push(Value::from(self.studios.get(id)));
+        }
+        Ok(hits)
+    }
+
+    // Also removes the studio's members and projects.
+    // Returns true if didn't exist.
+    pub async fn remove_studio_by_id(&mut self, auth_key: Option<&auth::Key>, id: &u64) -> Result<bool, RemoveByIdError> {
+        self.ensure_auth(auth::Op::Remove, auth_key).map_err(RemoveByIdError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| RemoveByIdError::Internal)?;
+        let Some(id) = self.studios_id_index.get(id) else {
+            return Ok(true);
+        };
+        self.write_remove::<DbRepr>(id.get()).await.map_err(|_| RemoveByIdError::Internal)?;
+        Ok(false)
+    }
+
+    // Adds users to the studio, or changes their role if they are already members.
+    pub async fn add_studio_members(&mut self, auth_key: Option<&auth::Key>, studio_id: u64, members: &[Member]) -> Result<(), WriteListError> {
+        self.ensure_auth(auth::Op::Write, auth_key).map_err(WriteListError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| WriteListError::Internal)?;
+        if self.studios_id_index.get(&studio_id).is_none() {
+            Err(WriteListError::NotFound)?
+        }
+        let members = members.iter().map(|member| (member.user_id, member.role.to_u8())).collect();
+        self.write_list(ListWrite::AddMembers { studio_id, members }).await
+    }
+
+    // Returns true if wasn't a member.
+    pub async fn remove_studio_member(&mut self, auth_key: Option<&auth::Key>, studio_id: u64, user_id: u64) -> Result<bool, WriteListError> {
+        self.ensure_auth(auth::Op::Remove, auth_key).map_err(WriteListError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| WriteListError::Internal)?;
+        if self.studios_members.search(&MemberKey { studio_id, user_id }).find().is_err() {
+            return Ok(true);
+        }
+        self.write_list(ListWrite::RemoveMember { studio_id, user_id }).await?;
+        Ok(false)
+    }
+
+    // Members sorted by their Scratch id.
+    pub fn studio_members(&self, auth_key: Option<&auth::Key>, studio_id: u64, query: &ListQuery) -> Result<Vec<Member>, GetByIdError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(GetByIdError::Auth)?;
+        if self.studios_id_index.get(&studio_id).is_none() {
+            Err(GetByIdError::NotFound)?
+        }
+        let Some(user_id) = query.after.map_or(Some(0), |after| after.checked_add(1)) else {
+            return Ok(Vec::new());
+        };
+        // Members with a role that can't be decoded are left out, `check` reports them.
+        Ok(iter_from!(self.studios_members, MemberKey { studio_id, user_id })
+            .take_while(|(key, _)| key.studio_id == studio_id)
+            .filter_map(|(key, role)| Some(Member { user_id: key.user_id, role: Role::from_u8(role)? }))
+            .take(list_limit(query.limit))
+            .collect())
+    }
+
+    // Projects that aren't added yet are recorded with the current time.
+    pub async fn add_studio_projects(&mut self, auth_key: Option<&auth::Key>, studio_id: u64, project_ids: &[u64]) -> Result<(), WriteListError> {
+        self.ensure_auth(auth::Op::Write, auth_key).map_err(WriteListError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| WriteListError::Internal)?;
+        if self.studios_id_index.get(&studio_id).is_none() {
+            Err(WriteListError::NotFound)?
+        }
+        let added = chrono::Utc::now().timestamp();
+        self.write_list(ListWrite::AddProjects { studio_id, project_ids: project_ids.to_vec(), added }).await
+    }
+
+    // Returns true if wasn't in the studio.
+    pub async fn remove_studio_project(&mut self, auth_key: Option<&auth::Key>, studio_id: u64, project_id: u64) -> Result<bool, WriteListError> {
+        self.ensure_auth(auth::Op::Remove, auth_key).map_err(WriteListError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| WriteListError::Internal)?;
+        if self.studios_projects.search(&ProjectKey { studio_id, project_id }).find().is_err() {
+            return Ok(true);
+        }
+        self.write_list(ListWrite::RemoveProject { studio_id, project_id }).await?;
+        Ok(false)
+    }
+
+    // Ids of the studio's projects, sorted. They can be fetched with `project_by_id` if they are stored.
+    pub fn studio_projects(&self, auth_key: Option<&auth::Key>, studio_id: u64, query: &ListQuery) -> Result<Vec<u64>, GetByIdError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(GetByIdError::Auth)?;
+        if self.studios_id_index.get(&studio_id).is_none() {
+            Err(GetByIdError::NotFound)?
+        }
+        let Some(project_id) = query.after.map_or(Some(0), |after| after.checked_add(1)) else {
+            return Ok(Vec::new());
+        };
+        Ok(iter_from!(self.studios_projects, ProjectKey { studio_id, project_id })
+            .take_while(|(key, _)| key.studio_id == studio_id)
+            .take(list_limit(query.limit))
+            .map(|(key, _)| key.project_id)
+            .collect())
+    }
+}
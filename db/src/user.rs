@@ -1,11 +1,15 @@
 use std::borrow::Cow;
 use binbuf::{BytesPtrConst, BytesPtr, Dynamic, Fixed};
-use crate::{auth, BindbError, BindbErrorKind, BindbErrorOp, InternalError};
+use crate::{auth, journal, list_limit, project, search::SearchBackend, BindbError, BindbErrorKind, BindbErrorOp, InternalError};
 use binbuf::impls::ArbNum;
 use super::Username as Name;
 use serde::{Serialize, Deserialize};
 use bincode::Decode;
 
+pub mod history;
+pub mod top;
+pub mod follow;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MeiliDoc {
     pub id: u64,
@@ -13,6 +17,16 @@ pub struct MeiliDoc {
     pub bio: String,
 }
 
+impl MeiliDoc {
+    pub fn from_db_value(id: u64, value: DbValue) -> Self {
+        Self {
+            id,
+            status: value.status,
+            bio: value.bio,
+        }
+    }
+}
+
 // #[derive(Clone, Debug, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
 // pub struct Stats {
     
@@ -103,10 +117,29 @@ binbuf::fixed! {
     impl Code for FixedData {}
 }
 
+// Key of a user's previous name, `idx` counts renames starting from 0.
+binbuf::fixed! {
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct PreviousNameKey {
+        pub user_id: u64,
+        pub idx: u16,
+    }
+
+    buf! { pub struct PreviousNameKeyBuf<P>(PreviousNameKey, P); }
+
+    impl I for PreviousNameKey {
+        type Buf<P> = PreviousNameKeyBuf<P>;
+    }
+    impl Code for PreviousNameKey {}
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
 pub enum AddError {
     Internal,
-    Auth(auth::EnsureAuthError)
+    Auth(auth::EnsureAuthError),
+    // Another stored user has the name, `user_id` is its Scratch id.
+    // Its record is out of date, it has to be written with its current name first.
+    NameTaken { user_id: u64 },
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
@@ -127,116 +160,512 @@ pub enum GetByNameError {
     NotFound,
 }
 
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum GetByIdError {
+    Auth(auth::EnsureAuthError),
+    NotFound,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ListError {
+    Auth(auth::EnsureAuthError),
+}
+
+// A page of users sorted by name, starting right after the `after` name.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct ListQuery {
+    pub after: Option<Name>,
+    pub limit: Option<u32>,
+}
+
+// Users with names in `from ..= to`, sorted by name. Both bounds are optional.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct NameRange {
+    pub from: Option<Name>,
+    pub to: Option<Name>,
+    pub limit: Option<u32>,
+}
+
 impl super::Value {
     unsafe fn add_user_raw<'a>(&mut self, data: Value<'a>) -> Result<u64, bindb::storage::indexed_dynamic::AddError> {
         self.users.add(&data.to_db_value())
     }
 
-    // Returns true if already exists.
+    // Points `name` at `id`, replacing the entry if the name is already taken.
+    unsafe fn set_user_name_index(&mut self, name: &Name, id: u64) -> Result<(), BindbErrorKind> {
+        let name = &name.folded();
+        match self.users_name_index.search(name).find() {
+            Ok(searched) => {
+                self.users_name_index.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+                self.users_name_index.add(name, &ArbNum::new(id)).map_err(BindbErrorKind::BinaryTreeAdd)?;
+            },
+            Err(searched) => {
+                self.users_name_index.add_searched(&searched, name, &ArbNum::new(id)).map_err(BindbErrorKind::BinaryTreeAdd)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Removes `name` only if it still points at `id`.
+    unsafe fn unset_user_name_index(&mut self, name: &Name, id: u64) -> Result<(), BindbErrorKind> {
+        let name = &name.folded();
+        if let Ok(searched) = self.users_name_index.search(name).find() {
+            if self.users_name_index.get_searched(&searched).unwrap() == id {
+                self.users_name_index.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Points `scratch_id` at `id`, replacing the entry if the id is already taken.
+    pub(crate) unsafe fn set_user_id_index(&mut self, scratch_id: u64, id: u64) -> Result<(), BindbErrorKind> {
+        match self.users_id_index.search(&scratch_id).find() {
+            Ok(searched) => {
+                self.users_id_index.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+                self.users_id_index.add(&scratch_id, &ArbNum::new(id)).map_err(BindbErrorKind::BinaryTreeAdd)?;
+            },
+            Err(searched) => {
+                self.users_id_index.add_searched(&searched, &scratch_id, &ArbNum::new(id)).map_err(BindbErrorKind::BinaryTreeAdd)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Removes `scratch_id` only if it still points at `id`.
+    unsafe fn unset_user_id_index(&mut self, scratch_id: u64, id: u64) -> Result<(), BindbErrorKind> {
+        if let Ok(searched) = self.users_id_index.search(&scratch_id).find() {
+            if self.users_id_index.get_searched(&searched).unwrap() == id {
+                self.users_id_index.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn previous_user_names_raw(&self, scratch_id: u64) -> Vec<Name> {
+        let mut names = Vec::new();
+        while let Some(name) = self.users_previous_names.get(&PreviousNameKey { user_id: scratch_id, idx: names.len() as u16 }) {
+            names.push(name);
+        }
+        names
+    }
+
+    // Removes the user's previous names, the last one first so an interrupted removal leaves a sequence from 0.
+    // Idempotent.
+    unsafe fn remove_user_previous_names(&mut self, scratch_id: u64) -> Result<(), BindbErrorKind> {
+        let len = self.previous_user_names_raw(scratch_id).len() as u16;
+        for idx in (0 .. len).rev() {
+            if let Ok(searched) = self.users_previous_names.search(&PreviousNameKey { user_id: scratch_id, idx }).find() {
+                self.users_previous_names.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn add_user_doc(&self, id: u64) -> Result<(), InternalError> {
+        let meili_doc = MeiliDoc::from_db_value(id, self.users.get(id));
+        self.search.index("users", &[meili_doc]).await.map_err(InternalError::Search)
+    }
+
+    async fn delete_user_doc(&self, id: u64) -> Result<(), InternalError> {
+        self.search.delete("users", id).await.map_err(InternalError::Search)
+    }
+
+    // Makes the indexes and search point at the stored entry `id`.
+    // Every step is idempotent, so it's also used to finish an interrupted add.
+    pub(crate) async fn apply_add_user(&mut self, id: u64) -> Result<(), InternalError> {
+        let data = self.users.get(id).fixed_data;
+        let e = |kind| InternalError::bindb(BindbErrorOp::AddUser, kind);
+        unsafe {
+            self.set_user_name_index(&data.name, id).map_err(e)?;
+            self.set_user_id_index(data.id, id).map_err(e)?;
+            self.set_user_top_index(&data, id).map_err(e)?;
+        }
+        self.add_user_doc(id).await
+    }
+
+    async fn rollback_add_user(&mut self, id: u64) -> Result<(), InternalError> {
+        let data = self.users.get(id).fixed_data;
+        let e = |kind| InternalError::bindb(BindbErrorOp::AddUser, kind);
+        unsafe {
+            self.unset_user_name_index(&data.name, id).map_err(e)?;
+            self.unset_user_id_index(data.id, id).map_err(e)?;
+            self.unset_user_top_index(&data, id).map_err(e)?;
+        }
+        self.delete_user_doc(id).await?;
+        self.finish_intent(BindbErrorOp::AddUser, Some(journal::Entry::User(id))).await;
+        Ok(())
+    }
+
+    // Moves the indexes and search from the stored entry `old_id` to `id`.
+    // If the name changed other than in case, the old one is added to the user's previous names.
+    // Every step is idempotent, so it's also used to finish an interrupted replace.
+    pub(crate) async fn apply_replace_user(&mut self, old_id: u64, id: u64) -> Result<(), InternalError> {
+        let old = self.users.get(old_id).fixed_data;
+        let data = self.users.get(id).fixed_data;
+        let e = |kind| InternalError::bindb(BindbErrorOp::ReplaceUser, kind);
+        unsafe {
+            self.unset_user_name_index(&old.name, old_id).map_err(e)?;
+            self.set_user_name_index(&data.name, id).map_err(e)?;
+            self.unset_user_id_index(old.id, old_id).map_err(e)?;
+            self.set_user_id_index(data.id, id).map_err(e)?;
+            self.unset_user_top_index(&old, old_id).map_err(e)?;
+            self.set_user_top_index(&data, id).map_err(e)?;
+        }
+        self.add_user_doc(id).await?;
+        self.delete_user_doc(old_id).await?;
+
+        let previous_names = self.previous_user_names_raw(data.id);
+        if old.name.folded() != data.name.folded() && previous_names.last() != Some(&old.name) {
+            let key = PreviousNameKey {
+                user_id: data.id,
+                idx: previous_names.len() as u16,
+            };
+            self.users_previous_names.add(&key, &old.name)
+                .map_err(|err| e(BindbErrorKind::BinaryTreeAdd(err)))?;
+        }
+        Ok(())
+    }
+
+    async fn rollback_replace_user(&mut self, old_id: u64, id: u64) -> Result<(), InternalError> {
+        let old = self.users.get(old_id).fixed_data;
+        let data = self.users.get(id).fixed_data;
+        let e = |kind| InternalError::bindb(BindbErrorOp::ReplaceUser, kind);
+        unsafe {
+            self.unset_user_name_index(&data.name, id).map_err(e)?;
+            self.set_user_name_index(&old.name, old_id).map_err(e)?;
+            self.unset_user_id_index(data.id, id).map_err(e)?;
+            self.set_user_id_index(old.id, old_id).map_err(e)?;
+            self.unset_user_top_index(&data, id).map_err(e)?;
+            self.set_user_top_index(&old, old_id).map_err(e)?;
+        }
+        self.add_user_doc(old_id).await?;
+        self.delete_user_doc(id).await?;
+        self.finish_intent(BindbErrorOp::ReplaceUser, Some(journal::Entry::User(id))).await;
+        Ok(())
+    }
+
+    // Removes the stored entry `id` from the indexes and search, along with the user's previous names and history.
+    // Every step is idempotent, so it's also used to finish an interrupted remove.
+    pub(crate) async fn apply_remove_user(&mut self, id: u64) -> Result<(), InternalError> {
+        let data = self.users.get(id).fixed_data;
+        let e = |kind| InternalError::bindb(BindbErrorOp::RemoveUserByName, kind);
+        self.delete_user_doc(id).await?;
+        unsafe {
+            self.unset_user_name_index(&data.name, id).map_err(e)?;
+            self.unset_user_id_index(data.id, id).map_err(e)?;
+            self.unset_user_top_index(&data, id).map_err(e)?;
+            self.remove_user_previous_names(data.id).map_err(e)?;
+            self.remove_user_history(data.id).map_err(e)?;
+        }
+        Ok(())
+    }
+
+    // Previous names and history that were already removed aren't restored.
+    async fn rollback_remove_user(&mut self, id: u64) -> Result<(), InternalError> {
+        self.apply_add_user(id).await?;
+        self.finish_intent(BindbErrorOp::RemoveUserByName, None).await;
+        Ok(())
+    }
+
+    // Stores `data` as a new entry and records the intent to write it.
+    // On failure nothing is left behind.
+    async fn add_user_entry<'a>(&mut self, op: BindbErrorOp, data: &Value<'a>, intent: impl FnOnce(u64) -> journal::Intent) -> Result<u64, ()> {
+        let id = match unsafe { self.add_user_raw(data.clone()) } {
+            Ok(id) => id,
+            Err(e) => {
+                self.send_bindb_error(op, BindbErrorKind::IndexedDynamicAdd(e)).await;
+                Err(())?
+            }
+        };
+        if let Err(e) = self.journal.write(&intent(id)) {
+            self.send_error(InternalError::Journal(e)).await;
+            if let Err(e) = unsafe { self.users.remove(id) } {
+                self.send_bindb_error(op, BindbErrorKind::IndexedDynamicRemove(e)).await;
+            }
+            Err(())?
+        }
+        Ok(id)
+    }
+
+    async fn append_user_history_now<'a>(&mut self, op: BindbErrorOp, data: &Value<'a>) {
+        if let Err(e) = unsafe { self.append_user_history(data.id, &history::Entry::now(data)) } {
+            self.send_bindb_error(op, e).await;
+        }
+    }
+
+    // Overwrites the user stored at `old_id`.
+    async fn replace_user<'a>(&mut self, old_id: u64, data: Value<'a>) -> Result<(), AddError> {
+        let id = self.add_user_entry(BindbErrorOp::ReplaceUser, &data, |id| journal::Intent::ReplaceUser { old_id, id })
+            .await
+            .map_err(|_| AddError::Internal)?;
+
+        if let Err(e) = self.apply_replace_user(old_id, id).await {
+            self.send_error(e).await;
+            if let Err(e) = self.rollback_replace_user(old_id, id).await {
+                self.send_error(e).await;
+                self.needs_recovery = true;
+            }
+            Err(AddError::Internal)?
+        }
+        self.finish_intent(BindbErrorOp::ReplaceUser, Some(journal::Entry::User(old_id))).await;
+        self.append_user_history_now(BindbErrorOp::ReplaceUser, &data).await;
+        Ok(())
+    }
+
+    // Scratch id of the stored user named `name`, if it's not `scratch_id`.
+    fn other_user_named(&self, name: &Name, scratch_id: u64) -> Option<u64> {
+        let id = self.users_name_index.get(&name.folded())?;
+        let user_id = self.users.get(id.unwrap()).fixed_data.id;
+        (user_id != scratch_id).then_some(user_id)
+    }
+
+    // Returns true if already exists, names are compared ignoring case.
+    // An existing user isn't overwritten, but the statistics of `data` are added to its history.
+    // A user with a known Scratch id but a new name is treated as renamed.
+    // Fails if another user has the name, see `AddError::NameTaken`.
     pub async fn add_user<'a>(&mut self, auth_key: Option<&auth::Key>, data: Value<'a>) -> Result<bool, AddError> {
         self.ensure_auth(auth::Op::Write, auth_key).map_err(AddError::Auth)?;
-        let searched = self.users_name_index.search(&data.name);
-        match searched.find() {
-            Ok(_) => Ok(true),
-            Err(searched) => {
-                let name = data.name.clone();
-                let id = match unsafe { self.add_user_raw(data.clone()) } {
-                    Ok(id) => id,
-                    Err(e) => {
-                        self.send_bindb_error(BindbErrorOp::AddUser, BindbErrorKind::IndexedDynamicAdd(e)).await;
-                        Err(AddError::Internal)?
-                    }
-                };
-
-                if let Err(e) = unsafe {
-                    self.users_name_index.add_searched(&searched, &name, &ArbNum::new(id))
-                } {
-                    self.send_bindb_error(BindbErrorOp::AddUser, BindbErrorKind::BinaryTreeAdd(e)).await;
-                    Err(AddError::Internal)?
-                }
-
-                let meili_doc = MeiliDoc {
-                    id,
-                    status: data.status.to_string(),
-                    bio: data.bio.to_string()
-                };
-
-                let info = match self.meili_client
-                    .index("users")
-                    .add_documents(&[meili_doc], None)
-                    .await
-                {
-                    Ok(info) => info,
-                    Err(e) => {
-                        self.send_meili_error(e).await;
-                        Err(AddError::Internal)?
-                    }
-                };
-                println!("Task info: {:?}", info);
-
-                Ok(false)
+        self.ensure_recovered().await.map_err(|_| AddError::Internal)?;
+        if let Some(user_id) = self.other_user_named(&data.name, data.id) {
+            Err(AddError::NameTaken { user_id })?
+        }
+        if self.users_name_index.get(&data.name.folded()).is_some() {
+            self.append_user_history_now(BindbErrorOp::AddUser, &data).await;
+            return Ok(true);
+        }
+        if let Some(old_id) = self.users_id_index.get(&data.id) {
+            self.replace_user(old_id.unwrap(), data).await?;
+            return Ok(true);
+        }
+
+        let id = self.add_user_entry(BindbErrorOp::AddUser, &data, |id| journal::Intent::AddUser { id })
+            .await
+            .map_err(|_| AddError::Internal)?;
+
+        if let Err(e) = self.apply_add_user(id).await {
+            self.send_error(e).await;
+            if let Err(e) = self.rollback_add_user(id).await {
+                self.send_error(e).await;
+                self.needs_recovery = true;
             }
+            Err(AddError::Internal)?
         }
+        self.finish_intent(BindbErrorOp::AddUser, None).await;
+        self.append_user_history_now(BindbErrorOp::AddUser, &data).await;
+        Ok(false)
+    }
+
+    // Inserts the user, or overwrites the existing user with the same Scratch id.
+    // Returns true if already exists. Fails if another user has the name, see `AddError::NameTaken`.
+    pub async fn upsert_user<'a>(&mut self, auth_key: Option<&auth::Key>, data: Value<'a>) -> Result<bool, AddError> {
+        self.ensure_auth(auth::Op::Write, auth_key).map_err(AddError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| AddError::Internal)?;
+        if let Some(user_id) = self.other_user_named(&data.name, data.id) {
+            Err(AddError::NameTaken { user_id })?
+        }
+        let old_id = match self.users_id_index.get(&data.id) {
+            Some(old_id) => old_id.unwrap(),
+            None => return self.add_user(auth_key, data).await,
+        };
+        self.replace_user(old_id, data).await?;
+        Ok(true)
     }
 
     pub fn user_by_name(&self, auth_key: Option<&auth::Key>, name: &Name) -> Result<Value<'static>, GetByNameError> {
         self.ensure_auth(auth::Op::Read, auth_key).map_err(GetByNameError::Auth)?;
-        self.users_name_index.get(name)
+        self.users_name_index.get(&name.folded())
             .map(|id| {
                 self.users.get(id.unwrap()).into()
             })
             .ok_or(GetByNameError::NotFound)
     }
 
+    pub fn user_by_id(&self, auth_key: Option<&auth::Key>, id: &u64) -> Result<Value<'static>, GetByIdError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(GetByIdError::Auth)?;
+        self.users_id_index.get(id)
+            .map(|id| {
+                self.users.get(id.unwrap()).into()
+            })
+            .ok_or(GetByIdError::NotFound)
+    }
+
+    pub fn previous_user_names(&self, auth_key: Option<&auth::Key>, name: &Name) -> Result<Vec<Name>, GetByNameError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(GetByNameError::Auth)?;
+        let id = self.users_name_index.get(&name.folded()).ok_or(GetByNameError::NotFound)?;
+        let scratch_id = self.users.get(id.unwrap()).fixed_data.id;
+        Ok(self.previous_user_names_raw(scratch_id))
+    }
+
+    // The name of the last user in a page is the `after` cursor of the next page.
+    // Names are compared ignoring case.
+    pub fn list_users(&self, auth_key: Option<&auth::Key>, query: &ListQuery) -> Result<Vec<Value<'static>>, ListError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(ListError::Auth)?;
+        let after = query.after.as_ref().map(Name::folded);
+        Ok(iter_from!(self.users_name_index, after.clone().unwrap_or(Name::EMPTY))
+            .skip_while(|(name, _)| after.as_ref() == Some(name))
+            .take(list_limit(query.limit))
+            .map(|(_, id)| self.users.get(id.unwrap()).into())
+            .collect())
+    }
+
+    // Names with the same prefix are next to each other in the index, so the walk stops at the first name without it.
+    // `query.after` pages through the results the same way as in `list_users`.
+    pub fn users_by_name_prefix(&self, auth_key: Option<&auth::Key>, prefix: &Name, query: &ListQuery) -> Result<Vec<Value<'static>>, ListError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(ListError::Auth)?;
+        let prefix = &prefix.folded();
+        let after = query.after.as_ref().map(Name::folded);
+        let from = match &after {
+            Some(after) if after > prefix => after.clone(),
+            _ => prefix.clone(),
+        };
+        Ok(iter_from!(self.users_name_index, from)
+            .skip_while(|(name, _)| after.as_ref() == Some(name))
+            .take_while(|(name, _)| name.starts_with(prefix))
+            .take(list_limit(query.limit))
+            .map(|(_, id)| self.users.get(id.unwrap()).into())
+            .collect())
+    }
+
+    pub fn users_by_name_range(&self, auth_key: Option<&auth::Key>, range: &NameRange) -> Result<Vec<Value<'static>>, ListError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(ListError::Auth)?;
+        let from = range.from.as_ref().map(Name::folded);
+        let to = range.to.as_ref().map(Name::folded);
+        Ok(iter_from!(self.users_name_index, from.unwrap_or(Name::EMPTY))
+            .take_while(|(name, _)| to.as_ref().map_or(true, |to| name <= to))
+            .take(list_limit(range.limit))
+            .map(|(_, id)| self.users.get(id.unwrap()).into())
+            .collect())
+    }
+
+    // Projects by the user, see `projects_by_author`.
+    pub fn user_projects(&self, auth_key: Option<&auth::Key>, name: &Name, query: &project::ByAuthorQuery) -> Result<Vec<project::Value>, GetByNameError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(GetByNameError::Auth)?;
+        let id = self.users_name_index.get(&name.folded()).ok_or(GetByNameError::NotFound)?;
+        let author_id = self.users.get(id.unwrap()).fixed_data.id;
+        Ok(self.projects_by_author_raw(author_id, query))
+    }
+
     pub async fn search_users<'a, 'b>(&'a self, auth_key: Option<&auth::Key>, query: &'b str) -> Result<Vec<Value<'static>>, SearchError> {
         self.ensure_auth(auth::Op::Read, auth_key).map_err(SearchError::Auth)?;
-        let res = self.meili_client.index("users")
-            .search()
-            .with_query(query)
-            .execute::<MeiliDoc>()
-            .await;
-
-        let res = match res {
-            Ok(res) => res,
+        let ids = match self.search.search("users", query, &[]).await {
+            Ok(ids) => ids,
             Err(e) => {
-                self.send_meili_error(e).await;
+                self.send_search_error(e).await;
                 Err(SearchError::Internal)?
             }
         };
 
-        let mut hits = Vec::with_capacity(res.hits.len());
-        for hit in res.hits {
-            hits.push(Value::from(self.users.get(hit.result.id)));
+        let mut hits = Vec::with_capacity(ids.len());
+        for id in ids {
+            hits.push(Value::from(self.users.get(id)));
         }
         Ok(hits)
     }
 
     pub async fn remove_user_by_name(&mut self, auth_key: Option<&auth::Key>, name: &Name) -> Result<bool, RemoveByNameError> {
         self.ensure_auth(auth::Op::Remove, auth_key).map_err(RemoveByNameError::Auth)?;
-        let searched = self.users_name_index.search(name);
-        match searched.find() {
-            Ok(searched) => {
-                let id = unsafe { self.users_name_index.get_searched(&searched).unwrap() };
-                let res = self.meili_client.index("users")
-                    .delete_document(id)
-                    .await;
-
-                if let Err(e) = res {
-                    self.send_meili_error(e).await;
-                    Err(RemoveByNameError::Internal)?
-                }
-
-                if let Err(e) = unsafe { self.users.remove(id) } {
-                    self.send_bindb_error(BindbErrorOp::RemoveUserByName, BindbErrorKind::IndexedDynamicRemove(e)).await;
-                    Err(RemoveByNameError::Internal)?
-                }
-                if let Err(e) = unsafe { self.users_name_index.remove_searched(&searched) } {
-                    self.send_bindb_error(BindbErrorOp::RemoveUserByName, BindbErrorKind::BinaryTreeRemove(e)).await;
-                    Err(RemoveByNameError::Internal)?
-                }
-                Ok(false)
-            },
-            Err(_) => Ok(true)
+        self.ensure_recovered().await.map_err(|_| RemoveByNameError::Internal)?;
+        let Some(id) = self.users_name_index.get(&name.folded()) else {
+            return Ok(true);
+        };
+        let id = id.unwrap();
+
+        if let Err(e) = self.journal.write(&journal::Intent::RemoveUser { id }) {
+            self.send_error(InternalError::Journal(e)).await;
+            Err(RemoveByNameError::Internal)?
+        }
+        if let Err(e) = self.apply_remove_user(id).await {
+            self.send_error(e).await;
+            if let Err(e) = self.rollback_remove_user(id).await {
+                self.send_error(e).await;
+                self.needs_recovery = true;
+            }
+            Err(RemoveByNameError::Internal)?
         }
+        self.finish_intent(BindbErrorOp::RemoveUserByName, Some(journal::Entry::User(id))).await;
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{dir, open, user};
+    use super::{history, AddError};
+
+    #[tokio::test]
+    async fn rename_keeps_previous_names() {
+        let dir = dir("user_rename");
+        let mut db = open(&dir).await;
+        db.add_user(None, user("griffpatch", 1)).await.unwrap();
+        assert!(db.upsert_user(None, user("griffpatch_alt", 1)).await.unwrap());
+
+        let name = "griffpatch_alt".parse().unwrap();
+        assert_eq!(db.user_by_id(None, &1).unwrap().name, name);
+        assert_eq!(db.previous_user_names(None, &name).unwrap(), ["griffpatch".parse().unwrap()]);
+        assert!(db.user_by_name(None, &"griffpatch".parse().unwrap()).is_err());
+
+        assert!(!db.remove_user_by_name(None, &name).await.unwrap());
+        assert!(db.users_previous_names.iter().next().is_none());
+        assert!(db.check(false).await.unwrap().issues.is_empty());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn case_change_isnt_a_rename() {
+        let dir = dir("user_case_change");
+        let mut db = open(&dir).await;
+        db.add_user(None, user("griffpatch", 1)).await.unwrap();
+        assert!(db.upsert_user(None, user("GriffPatch", 1)).await.unwrap());
+
+        let name = "griffpatch".parse().unwrap();
+        assert_eq!(db.user_by_name(None, &name).unwrap().name, "GriffPatch".parse().unwrap());
+        assert!(db.previous_user_names(None, &name).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn taken_name_is_rejected() {
+        let dir = dir("user_name_taken");
+        let mut db = open(&dir).await;
+        db.add_user(None, user("griffpatch", 1)).await.unwrap();
+        db.add_user(None, user("kaj", 2)).await.unwrap();
+
+        assert!(matches!(db.upsert_user(None, user("GriffPatch", 2)).await, Err(AddError::NameTaken { user_id: 1 })));
+        assert!(matches!(db.add_user(None, user("griffpatch", 3)).await, Err(AddError::NameTaken { user_id: 1 })));
+        assert_eq!(db.user_by_name(None, &"griffpatch".parse().unwrap()).unwrap().id, 1);
+        assert_eq!(db.user_by_id(None, &2).unwrap().name, "kaj".parse().unwrap());
+        assert!(db.user_by_id(None, &3).is_err());
+    }
+
+    #[tokio::test]
+    async fn history_is_matched_by_time_and_removed_with_the_user() {
+        let dir = dir("user_history");
+        let mut db = open(&dir).await;
+        db.add_user(None, user("griffpatch", 1)).await.unwrap();
+        db.add_user(None, user("kaj", 2)).await.unwrap();
+        // The clock went backwards between the snapshots.
+        let entry = |time| history::Entry { time, loves: 0, favorites: 0, views: 0, remixes: 0 };
+        unsafe { db.append_user_history(1, &entry(200)) }.unwrap();
+        unsafe { db.append_user_history(1, &entry(100)) }.unwrap();
+
+        let name = "griffpatch".parse().unwrap();
+        let range = history::Range { from: Some(50), to: Some(250) };
+        let times = db.user_history(None, &name, &range).unwrap().into_iter().map(|entry| entry.time).collect::<Vec<_>>();
+        assert_eq!(times, [200, 100]);
+
+        assert!(!db.remove_user_by_name(None, &name).await.unwrap());
+        assert!(db.users_history.iter().all(|(key, _)| key.user_id == 2));
+        assert_eq!(db.user_history(None, &"kaj".parse().unwrap(), &history::Range::default()).unwrap().len(), 1);
+        assert!(db.check(false).await.unwrap().issues.is_empty());
+    }
+
+    #[tokio::test]
+    async fn every_write_adds_a_history_entry() {
+        let dir = dir("user_history_writes");
+        let mut db = open(&dir).await;
+        assert!(!db.add_user(None, user("griffpatch", 1)).await.unwrap());
+        assert!(db.add_user(None, user("griffpatch", 1)).await.unwrap());
+        assert!(db.upsert_user(None, user("griffpatch", 1)).await.unwrap());
+
+        let history = db.user_history(None, &"griffpatch".parse().unwrap(), &history::Range::default()).unwrap();
+        assert_eq!(history.len(), 3);
+    }
+}
@@ -0,0 +1,163 @@
+use binbuf::impls::ArbNum;
+use crate::{auth, journal, list_limit, BindbErrorKind, BindbErrorOp, InternalError};
+use super::ListError;
+
+// An edge of the follow graph, seen from `user_id`.
+// In `users_followers` `other_id` follows `user_id`, in `users_following` `user_id` follows `other_id`.
+// The value is the time the edge was recorded.
+binbuf::fixed! {
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Key {
+        pub user_id: u64,
+        pub other_id: u64,
+    }
+
+    buf! { pub struct KeyBuf<P>(Key, P); }
+
+    impl I for Key {
+        type Buf<P> = KeyBuf<P>;
+    }
+    impl Code for Key {}
+}
+
+pub type Index = bindb::storage::BinaryTree<ArbNum<8, u64>, Key, i64>;
+
+// A page of user ids sorted, starting right after the `after` id.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct ListQuery {
+    pub after: Option<u64>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct Relation {
+    pub follows: bool,
+    pub followed_by: bool,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum WriteError {
+    Internal,
+    Auth(auth::EnsureAuthError),
+}
+
+// A change to the follow graph, recorded in the journal while it's written.
+// Both indexes are written for every edge, applying it again is harmless, so an interrupted change is finished by recovery.
+#[derive(Clone, Debug, bincode::Encode, bincode::Decode)]
+pub enum Write {
+    AddFollowers { user_id: u64, follower_ids: Vec<u64>, added: i64 },
+    RemoveFollower { user_id: u64, follower_id: u64 },
+}
+
+fn page<'a>(index: &'a Index, user_id: u64, query: &ListQuery) -> impl Iterator<Item = u64> + 'a {
+    // Nothing comes after the largest id.
+    let other_id = query.after.map_or(Some(0), |after| after.checked_add(1));
+    other_id.into_iter()
+        .flat_map(move |other_id| iter_from!(index, Key { user_id, other_id }))
+        .take_while(move |(key, _)| key.user_id == user_id)
+        .map(|(key, _)| key.other_id)
+}
+
+impl crate::Value {
+    // Adds `key` to the index if it's not there yet.
+    pub(crate) unsafe fn add_follow_edge(index: &mut Index, key: &Key, time: i64) -> Result<(), BindbErrorKind> {
+        if let Err(searched) = index.search(key).find() {
+            index.add_searched(&searched, key, &time).map_err(BindbErrorKind::BinaryTreeAdd)?;
+        }
+        Ok(())
+    }
+
+    // Removes `key` from the index if it's there.
+    unsafe fn remove_follow_edge(index: &mut Index, key: &Key) -> Result<(), BindbErrorKind> {
+        if let Ok(searched) = index.search(key).find() {
+            index.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+        }
+        Ok(())
+    }
+
+    // Every step is idempotent.
+    pub(crate) unsafe fn apply_follow_write(&mut self, write: &Write) -> Result<(), BindbErrorKind> {
+        match write {
+            &Write::AddFollowers { user_id, ref follower_ids, added } => {
+                for &follower_id in follower_ids {
+                    Self::add_follow_edge(&mut self.users_followers, &Key { user_id, other_id: follower_id }, added)?;
+                    Self::add_follow_edge(&mut self.users_following, &Key { user_id: follower_id, other_id: user_id }, added)?;
+                }
+            },
+            &Write::RemoveFollower { user_id, follower_id } => {
+                Self::remove_follow_edge(&mut self.users_followers, &Key { user_id, other_id: follower_id })?;
+                Self::remove_follow_edge(&mut self.users_following, &Key { user_id: follower_id, other_id: user_id })?;
+            },
+        }
+        Ok(())
+    }
+
+    // If applying fails, the intent stays in the journal and the write is finished by recovery.
+    async fn write_follows(&mut self, write: Write) -> Result<(), WriteError> {
+        if let Err(e) = self.journal.write(&journal::Intent::WriteFollows(write.clone())) {
+            self.send_error(InternalError::Journal(e)).await;
+            Err(WriteError::Internal)?
+        }
+        if let Err(e) = unsafe { self.apply_follow_write(&write) } {
+            self.send_bindb_error(BindbErrorOp::WriteFollows, e).await;
+            self.needs_recovery = true;
+            Err(WriteError::Internal)?
+        }
+        self.finish_intent(BindbErrorOp::WriteFollows, None).await;
+        Ok(())
+    }
+
+    // Records that every user in `follower_ids` follows `user_id`.
+    pub async fn add_user_followers(&mut self, auth_key: Option<&auth::Key>, user_id: u64, follower_ids: &[u64]) -> Result<(), WriteError> {
+        self.ensure_auth(auth::Op::Write, auth_key).map_err(WriteError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| WriteError::Internal)?;
+        let added = chrono::Utc::now().timestamp();
+        self.write_follows(Write::AddFollowers { user_id, follower_ids: follower_ids.to_vec(), added }).await
+    }
+
+    // Returns true if `follower_id` wasn't recorded as following `user_id`.
+    pub async fn remove_user_follower(&mut self, auth_key: Option<&auth::Key>, user_id: u64, follower_id: u64) -> Result<bool, WriteError> {
+        self.ensure_auth(auth::Op::Remove, auth_key).map_err(WriteError::Auth)?;
+        self.ensure_recovered().await.map_err(|_| WriteError::Internal)?;
+        let followed = self.users_followers.get(&Key { user_id, other_id: follower_id }).is_some()
+            || self.users_following.get(&Key { user_id: follower_id, other_id: user_id }).is_some();
+        if !followed {
+            return Ok(true);
+        }
+        self.write_follows(Write::RemoveFollower { user_id, follower_id }).await?;
+        Ok(false)
+    }
+
+    // Scratch ids of the users following `user_id`, sorted.
+    pub fn user_followers(&self, auth_key: Option<&auth::Key>, user_id: u64, query: &ListQuery) -> Result<Vec<u64>, ListError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(ListError::Auth)?;
+        Ok(page(&self.users_followers, user_id, query)
+            .take(list_limit(query.limit))
+            .collect())
+    }
+
+    // Scratch ids of the users `user_id` follows, sorted.
+    pub fn user_following(&self, auth_key: Option<&auth::Key>, user_id: u64, query: &ListQuery) -> Result<Vec<u64>, ListError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(ListError::Auth)?;
+        Ok(page(&self.users_following, user_id, query)
+            .take(list_limit(query.limit))
+            .collect())
+    }
+
+    // Scratch ids of the users that `user_id` follows and that follow `user_id` back, sorted.
+    pub fn user_mutual_follows(&self, auth_key: Option<&auth::Key>, user_id: u64, query: &ListQuery) -> Result<Vec<u64>, ListError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(ListError::Auth)?;
+        Ok(page(&self.users_following, user_id, query)
+            .filter(|other_id| self.users_followers.get(&Key { user_id, other_id: *other_id }).is_some())
+            .take(list_limit(query.limit))
+            .collect())
+    }
+
+    pub fn user_relation(&self, auth_key: Option<&auth::Key>, user_id: u64, other_id: u64) -> Result<Relation, ListError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(ListError::Auth)?;
+        Ok(Relation {
+            follows: self.users_following.get(&Key { user_id, other_id }).is_some(),
+            followed_by: self.users_followers.get(&Key { user_id, other_id }).is_some(),
+        })
+    }
+}
@@ -0,0 +1,118 @@
+use binbuf::impls::ArbNum;
+use crate::{auth, BindbErrorKind, Username as Name};
+use super::GetByNameError;
+
+// Key of a user's statistics snapshot, `idx` counts snapshots starting from 0.
+// Snapshots are ordered by `idx`, their times may go backwards with the clock.
+binbuf::fixed! {
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Key {
+        pub user_id: u64,
+        pub idx: u32,
+    }
+
+    buf! { pub struct KeyBuf<P>(Key, P); }
+
+    impl I for Key {
+        type Buf<P> = KeyBuf<P>;
+    }
+    impl Code for Key {}
+}
+
+binbuf::fixed! {
+    #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+    pub struct Entry {
+        // Unix timestamp (seconds).
+        pub time: i64,
+        pub loves: u32,
+        pub favorites: u32,
+        pub views: u32,
+        pub remixes: u32,
+    }
+
+    buf! { pub struct EntryBuf<P>(Entry, P); }
+
+    impl I for Entry {
+        type Buf<P> = EntryBuf<P>;
+    }
+    impl Code for Entry {}
+}
+
+impl Entry {
+    pub fn now<'a>(user: &super::Value<'a>) -> Self {
+        Self {
+            time: chrono::Utc::now().timestamp(),
+            loves: user.loves,
+            favorites: user.favorites,
+            views: user.views,
+            remixes: user.remixes,
+        }
+    }
+}
+
+pub type Store = bindb::storage::BinaryTree<ArbNum<4, u64>, Key, Entry>;
+
+// Both bounds are inclusive unix timestamps (seconds).
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct Range {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+impl crate::Value {
+    // Number of snapshots of the user, probed on `idx`.
+    fn user_history_len(&self, user_id: u64) -> u32 {
+        let exists = |idx| self.users_history.get(&Key { user_id, idx }).is_some();
+        if !exists(0) {
+            return 0;
+        }
+        let mut hi = 1;
+        while exists(hi) {
+            hi *= 2;
+        }
+        let mut lo = hi / 2;
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if exists(mid) {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        hi
+    }
+
+    pub(crate) unsafe fn append_user_history(&mut self, user_id: u64, entry: &Entry) -> Result<(), BindbErrorKind> {
+        let idx = self.user_history_len(user_id);
+        self.users_history.add(&Key { user_id, idx }, entry)
+            .map(|_| ())
+            .map_err(BindbErrorKind::BinaryTreeAdd)
+    }
+
+    // Removes the user's snapshots, the last one first so an interrupted removal leaves a sequence from 0.
+    // Idempotent.
+    pub(crate) unsafe fn remove_user_history(&mut self, user_id: u64) -> Result<(), BindbErrorKind> {
+        let keys = iter_from!(self.users_history, Key { user_id, idx: 0 })
+            .take_while(|(key, _)| key.user_id == user_id)
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        for key in keys.into_iter().rev() {
+            if let Ok(searched) = self.users_history.search(&key).find() {
+                self.users_history.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn user_history(&self, auth_key: Option<&auth::Key>, name: &Name, range: &Range) -> Result<Vec<Entry>, GetByNameError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(GetByNameError::Auth)?;
+        let id = self.users_name_index.get(&name.folded()).ok_or(GetByNameError::NotFound)?;
+        let user_id = self.users.get(id.unwrap()).fixed_data.id;
+        // Times aren't ordered, so every snapshot is checked against the range.
+        Ok(iter_from!(self.users_history, Key { user_id, idx: 0 })
+            .take_while(|(key, _)| key.user_id == user_id)
+            .map(|(_, entry)| entry)
+            .filter(|entry| range.from.map_or(true, |from| entry.time >= from) && range.to.map_or(true, |to| entry.time <= to))
+            .collect())
+    }
+}
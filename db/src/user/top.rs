@@ -0,0 +1,125 @@
+use std::str::FromStr;
+use binbuf::impls::ArbNum;
+use crate::{auth, list_limit, BindbErrorKind};
+use super::{FixedData, ListError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+#[serde(rename_all = "snake_case")]
+pub enum Stat {
+    Loves,
+    Favorites,
+    Views,
+    Remixes,
+}
+
+impl Stat {
+    pub const ALL: [Self; 4] = [Self::Loves, Self::Favorites, Self::Views, Self::Remixes];
+
+    fn idx(self) -> u8 {
+        match self {
+            Self::Loves => 0,
+            Self::Favorites => 1,
+            Self::Views => 2,
+            Self::Remixes => 3,
+        }
+    }
+
+    fn of(self, data: &FixedData) -> u32 {
+        match self {
+            Self::Loves => data.loves,
+            Self::Favorites => data.favorites,
+            Self::Views => data.views,
+            Self::Remixes => data.remixes,
+        }
+    }
+}
+
+impl FromStr for Stat {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "loves" => Ok(Self::Loves),
+            "favorites" => Ok(Self::Favorites),
+            "views" => Ok(Self::Views),
+            "remixes" => Ok(Self::Remixes),
+            _ => Err(())
+        }
+    }
+}
+
+// Key of a user in the leaderboard of a statistic.
+// `rank` is `u32::MAX - value`, so users with the highest value come first.
+// Users with equal values are ordered by their Scratch id.
+binbuf::fixed! {
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Key {
+        pub stat: u8,
+        pub rank: u32,
+        pub user_id: u64,
+    }
+
+    buf! { pub struct KeyBuf<P>(Key, P); }
+
+    impl I for Key {
+        type Buf<P> = KeyBuf<P>;
+    }
+    impl Code for Key {}
+}
+
+impl Key {
+    pub fn new(stat: Stat, data: &FixedData) -> Self {
+        Self {
+            stat: stat.idx(),
+            rank: u32::MAX - stat.of(data),
+            user_id: data.id,
+        }
+    }
+}
+
+pub type Index = bindb::storage::BinaryTree<ArbNum<4, u64>, Key, ArbNum<4, u64>>;
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub struct Query {
+    pub limit: Option<u32>,
+}
+
+impl crate::Value {
+    // Puts the stored user `id` into every leaderboard, replacing entries with the same key.
+    pub(crate) unsafe fn set_user_top_index(&mut self, data: &FixedData, id: u64) -> Result<(), BindbErrorKind> {
+        for stat in Stat::ALL {
+            let key = Key::new(stat, data);
+            match self.users_top_index.search(&key).find() {
+                Ok(searched) => {
+                    self.users_top_index.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+                    self.users_top_index.add(&key, &ArbNum::new(id)).map_err(BindbErrorKind::BinaryTreeAdd)?;
+                },
+                Err(searched) => {
+                    self.users_top_index.add_searched(&searched, &key, &ArbNum::new(id)).map_err(BindbErrorKind::BinaryTreeAdd)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Removes the entries of `data` that still point at the stored user `id`.
+    pub(crate) unsafe fn unset_user_top_index(&mut self, data: &FixedData, id: u64) -> Result<(), BindbErrorKind> {
+        for stat in Stat::ALL {
+            if let Ok(searched) = self.users_top_index.search(&Key::new(stat, data)).find() {
+                if self.users_top_index.get_searched(&searched).unwrap() == id {
+                    self.users_top_index.remove_searched(&searched).map_err(BindbErrorKind::BinaryTreeRemove)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn top_users(&self, auth_key: Option<&auth::Key>, stat: Stat, query: &Query) -> Result<Vec<super::Value<'static>>, ListError> {
+        self.ensure_auth(auth::Op::Read, auth_key).map_err(ListError::Auth)?;
+        let stat = stat.idx();
+        Ok(iter_from!(self.users_top_index, Key { stat, rank: 0, user_id: 0 })
+            .take_while(|(key, _)| key.stat == stat)
+            .take(list_limit(query.limit))
+            .map(|(_, id)| self.users.get(id.unwrap()).into())
+            .collect())
+    }
+}
@@ -1,10 +1,10 @@
 
-use std::{fmt::Display, str::FromStr};
+use std::{cmp::Ordering, fmt::Display, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 
 binbuf::fixed! {
-    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, bincode::Encode, bincode::Decode)]
+    #[derive(Clone, Debug, PartialEq, Eq, bincode::Encode, bincode::Decode)]
     pub struct Value {
         len: u8,
         content: [u8; 20],
@@ -40,6 +40,10 @@ impl Value {
     pub unsafe fn from_raw(len: u8, content: [u8; 20]) -> Self {
         Self { len, content }
     }
+
+    // Ordered before every other name, so index walks can start at it.
+    pub const EMPTY: Self = Self { len: 0, content: [0; 20] };
+
     // Sorted array
     pub const CHARS: [char; 64] = [
         '-',
@@ -58,6 +62,28 @@ impl Value {
     ];
 
 
+    // Indices of the chars in `CHARS`.
+    pub fn chars(&self) -> &[u8] {
+        &self.content[.. self.len as usize]
+    }
+
+    // Same name with uppercase letters replaced by lowercase ones.
+    pub fn folded(&self) -> Self {
+        const UPPER: std::ops::RangeInclusive<u8> = 11 ..= 36;
+        const TO_LOWER: u8 = 27;
+        let mut folded = self.clone();
+        for ch in &mut folded.content[.. self.len as usize] {
+            if UPPER.contains(ch) {
+                *ch += TO_LOWER;
+            }
+        }
+        folded
+    }
+
+    pub fn starts_with(&self, prefix: &Self) -> bool {
+        self.chars().starts_with(prefix.chars())
+    }
+
     // fn char(self, idx: u8) -> u8 {
     //     let mut n = u128::from_be_bytes(self.0);
     //     n >>= 6 * idx;
@@ -65,6 +91,20 @@ impl Value {
     // }
 }
 
+// `CHARS` is sorted, so comparing char indices gives the same order as comparing the strings.
+// This makes names with a common prefix neighbours in the name index.
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.chars().cmp(other.chars())
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl FromStr for Value {
     type Err = ();
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -160,4 +200,48 @@ impl binbuf::fixed::Decode for Value {
             Self { len, content }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::Value;
+
+    fn name(s: &str) -> Value {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn folded_lowercases_every_char() {
+        for ch in Value::CHARS {
+            let folded = name(&ch.to_string()).folded();
+            assert_eq!(folded.to_string(), ch.to_ascii_lowercase().to_string());
+        }
+        assert_eq!(name("Griff_Patch-2").folded(), name("griff_patch-2"));
+    }
+
+    #[test]
+    fn ord_matches_string_order() {
+        let mut names = ["b", "griffpatch", "Griffpatch", "a", "griff", "_", "-1", "0", "ab", "Z"].map(name);
+        names.sort();
+        let mut strings = names.iter().map(Value::to_string).collect::<Vec<_>>();
+        strings.sort();
+        assert_eq!(names.iter().map(Value::to_string).collect::<Vec<_>>(), strings);
+        assert!(Value::EMPTY < name("-"));
+    }
+
+    #[test]
+    fn prefixes_sort_first() {
+        assert!(name("griff") < name("griffpatch"));
+        assert!(name("griffpatch") < name("griffz"));
+        assert!(name("griffz") < name("grig"));
+    }
+
+    #[test]
+    fn starts_with() {
+        assert!(name("griffpatch").starts_with(&name("griff")));
+        assert!(name("griffpatch").starts_with(&name("griffpatch")));
+        assert!(name("griffpatch").starts_with(&Value::EMPTY));
+        assert!(!name("griff").starts_with(&name("griffpatch")));
+        assert!(!name("Griffpatch").starts_with(&name("griff")));
+        assert!(name("Griffpatch").folded().starts_with(&name("griff")));
+    }
+}
@@ -0,0 +1,34 @@
+use std::{path::PathBuf, sync::Arc, time::{SystemTime, UNIX_EPOCH}};
+use tokio::sync::RwLock;
+use warp::{reject::Rejection, Filter};
+use crate::{auth_key_filter, InoutFormat, OptionAuthKey};
+use lib::admin::*;
+
+pub fn filter(db: Arc<RwLock<db::Value>>, backup_dir: Option<PathBuf>)
+-> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path!("backup" / InoutFormat)
+        .and(warp::post())
+        .and(auth_key_filter())
+        .then({
+            let db = db.clone();
+            move |out_format: InoutFormat, auth_key: OptionAuthKey| {
+                let db = (&db).clone();
+                let backup_dir = backup_dir.clone();
+                async move {
+                    let out = match backup_dir {
+                        // Readers can keep going, writers wait until the copy is done.
+                        Some(backup_dir) => {
+                            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+                            let dest = backup_dir.join(now.to_string());
+                            db::Value::backup(db.read_owned().await, auth_key.as_ref(), dest).await
+                                .map_err(BackupError::Backup)
+                        },
+                        None => Err(BackupError::Disabled),
+                    };
+                    out_format.encode_val_to_response(&out)
+                }
+            }
+        })
+}
+
+// admin/backup/json
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::{reject::Rejection, Filter};
+use crate::{auth_key_filter, InoutFormat, OptionAuthKey};
+use db::comment::Owner;
+use lib::comments::*;
+
+pub fn filter(db: Arc<RwLock<db::Value>>)
+-> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path!("get_by_id" / Owner / u64 / InoutFormat)
+        .and(warp::get())
+        .and(auth_key_filter())
+        .then({
+            let db = db.clone();
+            move |owner: Owner, id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                let db = (&db).clone();
+                async move {
+                    let out = db.read().await.comment_by_id(auth_key.as_ref(), owner, id)
+                        .map_err(GetByIdError::Get);
+                    out_format.encode_val_to_response(&out)
+                }
+            }
+        })
+    .or(
+        warp::path!("threads" / Owner / u64 / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::comment::ThreadsQuery>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |owner: Owner, owner_id: u64, out_format: InoutFormat, query: db::comment::ThreadsQuery, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.comment_threads(auth_key.as_ref(), owner, owner_id, &query)
+                            .map_err(ThreadsError::List);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("search" / String / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::comment::SearchFilter>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |query: String, out_format: InoutFormat, filter: db::comment::SearchFilter, auth_key: OptionAuthKey| {
+                    let db = db.clone();
+                    async move {
+                        let out = db.read().await.search_comments(auth_key.as_ref(), &query, &filter)
+                            .await
+                            .map_err(SearchError::Search);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("by_author" / db::Username / InoutFormat)
+            .and(warp::get())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |author_name: db::Username, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = db.clone();
+                    async move {
+                        let filter = db::comment::SearchFilter { author_name: Some(author_name), ..Default::default() };
+                        let out = db.read().await.search_comments(auth_key.as_ref(), "", &filter)
+                            .await
+                            .map_err(SearchError::Search);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("write" / InoutFormat / InoutFormat)
+            .and(warp::post())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes(&body) {
+                            Ok(data) => db.write().await.add_comment(auth_key.as_ref(), data).await
+                                .map_err(WriteError::Add),
+                            Err(e) => Err(WriteError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("write" / InoutFormat / InoutFormat)
+            .and(warp::put())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes(&body) {
+                            Ok(data) => db.write().await.upsert_comment(auth_key.as_ref(), data).await
+                                .map_err(WriteError::Add),
+                            Err(e) => Err(WriteError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("remove_by_id" / Owner / u64 / InoutFormat)
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |owner: Owner, id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.write().await.remove_comment_by_id(auth_key.as_ref(), owner, id)
+                            .await
+                            .map_err(RemoveByIdError::Remove);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+}
+
+// comments/threads/project/60917032/json
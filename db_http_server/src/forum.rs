@@ -0,0 +1,206 @@
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::{reject::Rejection, Filter};
+use crate::{auth_key_filter, InoutFormat, OptionAuthKey};
+use lib::forum::*;
+
+pub fn filter(db: Arc<RwLock<db::Value>>)
+-> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path!("topics" / "get_by_id" / u64 / InoutFormat)
+        .and(warp::get())
+        .and(auth_key_filter())
+        .then({
+            let db = db.clone();
+            move |id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                let db = (&db).clone();
+                async move {
+                    let out = db.read().await.forum_topic_by_id(auth_key.as_ref(), &id)
+                        .map_err(TopicGetByIdError::Get);
+                    out_format.encode_val_to_response(&out)
+                }
+            }
+        })
+    .or(
+        warp::path!("topics" / "by_category" / u64 / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::forum::ListQuery>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, out_format: InoutFormat, query: db::forum::ListQuery, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.forum_topics_by_category(auth_key.as_ref(), id, &query)
+                            .map_err(ByCategoryError::List);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("topics" / "write" / InoutFormat / InoutFormat)
+            .and(warp::post())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes(&body) {
+                            Ok(data) => db.write().await.add_forum_topic(auth_key.as_ref(), data).await
+                                .map_err(TopicWriteError::Add),
+                            Err(e) => Err(TopicWriteError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("topics" / "write" / InoutFormat / InoutFormat)
+            .and(warp::put())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes(&body) {
+                            Ok(data) => db.write().await.upsert_forum_topic(auth_key.as_ref(), data).await
+                                .map_err(TopicWriteError::Add),
+                            Err(e) => Err(TopicWriteError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("topics" / "remove_by_id" / u64 / InoutFormat)
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.write().await.remove_forum_topic_by_id(auth_key.as_ref(), &id)
+                            .await
+                            .map_err(TopicRemoveByIdError::Remove);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("posts" / "get_by_id" / u64 / InoutFormat)
+            .and(warp::get())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.forum_post_by_id(auth_key.as_ref(), &id)
+                            .map_err(PostGetByIdError::Get);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("posts" / "by_topic" / u64 / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::forum::ListQuery>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, out_format: InoutFormat, query: db::forum::ListQuery, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.forum_posts_by_topic(auth_key.as_ref(), id, &query)
+                            .map_err(ByTopicError::List);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("posts" / "search" / String / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::forum::post::SearchFilter>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |query: String, out_format: InoutFormat, filter: db::forum::post::SearchFilter, auth_key: OptionAuthKey| {
+                    let db = db.clone();
+                    async move {
+                        let out = db.read().await.search_forum_posts(auth_key.as_ref(), &query, &filter)
+                            .await
+                            .map_err(PostSearchError::Search);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("posts" / "write" / InoutFormat / InoutFormat)
+            .and(warp::post())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes(&body) {
+                            Ok(data) => db.write().await.add_forum_post(auth_key.as_ref(), data).await
+                                .map_err(PostWriteError::Add),
+                            Err(e) => Err(PostWriteError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("posts" / "write" / InoutFormat / InoutFormat)
+            .and(warp::put())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes(&body) {
+                            Ok(data) => db.write().await.upsert_forum_post(auth_key.as_ref(), data).await
+                                .map_err(PostWriteError::Add),
+                            Err(e) => Err(PostWriteError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("posts" / "remove_by_id" / u64 / InoutFormat)
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.write().await.remove_forum_post_by_id(auth_key.as_ref(), &id)
+                            .await
+                            .map_err(PostRemoveByIdError::Remove);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+}
+
+// forum/topics/by_category/31/json
+// forum/posts/by_topic/6368130/json
@@ -3,7 +3,7 @@
 use std::borrow::Cow;
 use std::convert::Infallible;
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::{rc::Rc, str::FromStr};
 pub use db::OpenMode;
@@ -11,10 +11,14 @@ use tokio::sync::{mpsc, RwLock};
 use warp::{serve, Filter};
 use warp::filters::path::param as warp_param;
 use lib::{inout_format, InoutFormat};
-use meilisearch_sdk::client::Client as MeiliClient;
 
 pub mod config;
 mod users;
+mod projects;
+mod studios;
+mod forum;
+mod comments;
+mod admin;
 
 // mod tests;
 
@@ -42,11 +46,36 @@ fn auth_key_filter()
         .unify()
 }
 
-fn router(db: Arc<RwLock<db::Value>>)
+fn router(db: Arc<RwLock<db::Value>>, backup_dir: Option<PathBuf>)
 -> impl Filter<Extract = (impl warp::Reply,), Error = Infallible> + Clone + Send + Sync + Sized + 'static {
         warp::path("users").and(
             users::filter(db.clone())
         )
+        .or(
+            warp::path("projects").and(
+                projects::filter(db.clone())
+            )
+        )
+        .or(
+            warp::path("studios").and(
+                studios::filter(db.clone())
+            )
+        )
+        .or(
+            warp::path("forum").and(
+                forum::filter(db.clone())
+            )
+        )
+        .or(
+            warp::path("comments").and(
+                comments::filter(db.clone())
+            )
+        )
+        .or(
+            warp::path("admin").and(
+                admin::filter(db.clone(), backup_dir)
+            )
+        )
         .or(
             warp::any()
             .map(|| "Hello world!")
@@ -91,20 +120,20 @@ fn router(db: Arc<RwLock<db::Value>>)
 pub struct Init {
     pub db: Arc<RwLock<db::Value>>,
     pub addr: SocketAddr,
+    pub backup_dir: Option<PathBuf>,
 }
 
 impl Init {
     pub async fn run(self) {
-        let filter = router(self.db);
+        let filter = router(self.db, self.backup_dir);
         warp::serve(filter)
             .run(self.addr)
             .await
     }
 }
 
-pub async fn init_with_config(config: config::Run) -> Init {
-    let meili_client = MeiliClient::new(config.meili_addr, Some(config.meili_key)).unwrap();
-
+// Fails if the database can't be opened, like when another process has it open.
+pub async fn init_with_config(config: config::Run) -> Result<Init, db::OpenError> {
     let (error_sender, mut error_receiver) = mpsc::channel(20);
     let _error_handle = tokio::spawn(async move {
         while let Some(err) = error_receiver.recv().await {
@@ -112,20 +141,18 @@ pub async fn init_with_config(config: config::Run) -> Init {
         }
     });
 
-    tokio::fs::create_dir_all(&config.db_path).await.unwrap();
-
     let mut db = unsafe {
         db::Value::open(
-            meili_client,
             &config.db_path,
-            config.mode,
             config.db,
             error_sender,
-        ).unwrap()
+        ).await?
     };
+    db.init_search().await.unwrap();
     let db = Arc::new(RwLock::new(db));
-    Init {
+    Ok(Init {
         db,
-        addr: config.addr
-    }
+        addr: config.addr,
+        backup_dir: config.backup_dir,
+    })
 }
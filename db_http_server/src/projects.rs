@@ -0,0 +1,117 @@
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::{reject::Rejection, Filter};
+use crate::{auth_key_filter, InoutFormat, OptionAuthKey};
+use lib::projects::*;
+
+pub fn filter(db: Arc<RwLock<db::Value>>)
+-> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path!("get_by_id" / u64 / InoutFormat)
+        .and(warp::get())
+        .and(auth_key_filter())
+        .then({
+            let db = db.clone();
+            move |id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                let db = (&db).clone();
+                async move {
+                    let out = db.read().await.project_by_id(auth_key.as_ref(), &id)
+                        .map_err(GetByIdError::Get);
+                    out_format.encode_val_to_response(&out)
+                }
+            }
+        })
+    .or(
+        warp::path!("by_author" / u64 / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::project::ByAuthorQuery>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |author_id: u64, out_format: InoutFormat, query: db::project::ByAuthorQuery, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.projects_by_author(auth_key.as_ref(), author_id, &query)
+                            .map_err(ByAuthorError::Get);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("search" / String / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::project::SearchFilter>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |query: String, out_format: InoutFormat, filter: db::project::SearchFilter, auth_key: OptionAuthKey| {
+                    let db = db.clone();
+                    async move {
+                        let out = db.read().await.search_projects(auth_key.as_ref(), &query, &filter)
+                            .await
+                            .map_err(SearchError::Search);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("write" / InoutFormat / InoutFormat)
+            .and(warp::post())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes(&body) {
+                            Ok(data) => db.write().await.add_project(auth_key.as_ref(), data).await
+                                .map_err(WriteError::Add),
+                            Err(e) => Err(WriteError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("write" / InoutFormat / InoutFormat)
+            .and(warp::put())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes(&body) {
+                            Ok(data) => db.write().await.upsert_project(auth_key.as_ref(), data).await
+                                .map_err(WriteError::Add),
+                            Err(e) => Err(WriteError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("remove_by_id" / u64 / InoutFormat)
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.write().await.remove_project_by_id(auth_key.as_ref(), &id)
+                            .await
+                            .map_err(RemoveByIdError::Remove);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+}
+
+// projects/get_by_id/60917032/json
+// projects/write/json/json
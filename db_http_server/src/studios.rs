@@ -0,0 +1,205 @@
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::{reject::Rejection, Filter};
+use crate::{auth_key_filter, InoutFormat, OptionAuthKey};
+use lib::studios::*;
+
+pub fn filter(db: Arc<RwLock<db::Value>>)
+-> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path!("get_by_id" / u64 / InoutFormat)
+        .and(warp::get())
+        .and(auth_key_filter())
+        .then({
+            let db = db.clone();
+            move |id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                let db = (&db).clone();
+                async move {
+                    let out = db.read().await.studio_by_id(auth_key.as_ref(), &id)
+                        .map_err(GetByIdError::Get);
+                    out_format.encode_val_to_response(&out)
+                }
+            }
+        })
+    .or(
+        warp::path!("search" / String / InoutFormat)
+            .and(warp::get())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |query: String, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = db.clone();
+                    async move {
+                        let out = db.read().await.search_studios(auth_key.as_ref(), &query)
+                            .await
+                            .map_err(SearchError::Search);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("members" / u64 / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::studio::ListQuery>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, out_format: InoutFormat, query: db::studio::ListQuery, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.studio_members(auth_key.as_ref(), id, &query)
+                            .map_err(GetByIdError::Get);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("projects" / u64 / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::studio::ListQuery>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, out_format: InoutFormat, query: db::studio::ListQuery, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.studio_projects(auth_key.as_ref(), id, &query)
+                            .map_err(GetByIdError::Get);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("write" / InoutFormat / InoutFormat)
+            .and(warp::post())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes(&body) {
+                            Ok(data) => db.write().await.add_studio(auth_key.as_ref(), data).await
+                                .map_err(WriteError::Add),
+                            Err(e) => Err(WriteError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("write" / InoutFormat / InoutFormat)
+            .and(warp::put())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes(&body) {
+                            Ok(data) => db.write().await.upsert_studio(auth_key.as_ref(), data).await
+                                .map_err(WriteError::Add),
+                            Err(e) => Err(WriteError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("members" / u64 / InoutFormat / InoutFormat)
+            .and(warp::post())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes::<Vec<db::studio::Member>>(&body) {
+                            Ok(members) => db.write().await.add_studio_members(auth_key.as_ref(), id, &members).await
+                                .map_err(WriteListError::Write),
+                            Err(e) => Err(WriteListError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("projects" / u64 / InoutFormat / InoutFormat)
+            .and(warp::post())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes::<Vec<u64>>(&body) {
+                            Ok(project_ids) => db.write().await.add_studio_projects(auth_key.as_ref(), id, &project_ids).await
+                                .map_err(WriteListError::Write),
+                            Err(e) => Err(WriteListError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("remove_by_id" / u64 / InoutFormat)
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.write().await.remove_studio_by_id(auth_key.as_ref(), &id)
+                            .await
+                            .map_err(RemoveByIdError::Remove);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("remove_member" / u64 / u64 / InoutFormat)
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, user_id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.write().await.remove_studio_member(auth_key.as_ref(), id, user_id)
+                            .await
+                            .map_err(RemoveFromListError::Remove);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("remove_project" / u64 / u64 / InoutFormat)
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, project_id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.write().await.remove_studio_project(auth_key.as_ref(), id, project_id)
+                            .await
+                            .map_err(RemoveFromListError::Remove);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+}
+
+// studios/get_by_id/34104948/json
+// studios/members/34104948/json
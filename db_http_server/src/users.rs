@@ -21,6 +21,207 @@ pub fn filter(db: Arc<RwLock<db::Value>>)
                 }
             }
         })
+    .or(
+        warp::path!("get_by_id" / u64 / InoutFormat)
+            .and(warp::get())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.user_by_id(auth_key.as_ref(), &id)
+                            .map_err(GetByIdError::Get);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("previous_names" / DbUsername / InoutFormat)
+            .and(warp::get())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |name, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.previous_user_names(auth_key.as_ref(), &name)
+                            .map_err(PreviousNamesError::Get);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("projects" / DbUsername / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::project::ByAuthorQuery>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |name, out_format: InoutFormat, query: db::project::ByAuthorQuery, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.user_projects(auth_key.as_ref(), &name, &query)
+                            .map_err(ProjectsError::Get);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("history" / DbUsername / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::user::history::Range>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |name, out_format: InoutFormat, range: db::user::history::Range, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.user_history(auth_key.as_ref(), &name, &range)
+                            .map_err(HistoryError::Get);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("list" / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::user::ListQuery>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |out_format: InoutFormat, query: db::user::ListQuery, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.list_users(auth_key.as_ref(), &query)
+                            .map_err(ListError::List);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("by_name_prefix" / DbUsername / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::user::ListQuery>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |prefix, out_format: InoutFormat, query: db::user::ListQuery, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.users_by_name_prefix(auth_key.as_ref(), &prefix, &query)
+                            .map_err(ListError::List);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("by_name_range" / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::user::NameRange>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |out_format: InoutFormat, range: db::user::NameRange, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.users_by_name_range(auth_key.as_ref(), &range)
+                            .map_err(ListError::List);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("top" / db::user::top::Stat / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::user::top::Query>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |stat, out_format: InoutFormat, query: db::user::top::Query, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.top_users(auth_key.as_ref(), stat, &query)
+                            .map_err(ListError::List);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("followers" / u64 / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::user::follow::ListQuery>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, out_format: InoutFormat, query: db::user::follow::ListQuery, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.user_followers(auth_key.as_ref(), id, &query)
+                            .map_err(ListError::List);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("following" / u64 / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::user::follow::ListQuery>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, out_format: InoutFormat, query: db::user::follow::ListQuery, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.user_following(auth_key.as_ref(), id, &query)
+                            .map_err(ListError::List);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("mutual_follows" / u64 / InoutFormat)
+            .and(warp::get())
+            .and(warp::query::<db::user::follow::ListQuery>())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, out_format: InoutFormat, query: db::user::follow::ListQuery, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.user_mutual_follows(auth_key.as_ref(), id, &query)
+                            .map_err(ListError::List);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("relation" / u64 / u64 / InoutFormat)
+            .and(warp::get())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, other_id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.read().await.user_relation(auth_key.as_ref(), id, other_id)
+                            .map_err(ListError::List);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
     .or(
         warp::path!("search" / String / InoutFormat)
             .and(warp::get())
@@ -61,6 +262,26 @@ pub fn filter(db: Arc<RwLock<db::Value>>)
                 }
             })
     )
+    .or(
+        warp::path!("write" / InoutFormat / InoutFormat)
+            .and(warp::put())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes(&body) {
+                            Ok(data) => db.write().await.upsert_user(auth_key.as_ref(), data).await
+                                .map_err(WriteError::Add),
+                            Err(e) => Err(WriteError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
     .or(
         warp::path!("remove_by_name" / DbUsername / InoutFormat)
             .and(auth_key_filter())
@@ -77,9 +298,46 @@ pub fn filter(db: Arc<RwLock<db::Value>>)
                 }
             })
     )
+    .or(
+        warp::path!("followers" / u64 / InoutFormat / InoutFormat)
+            .and(warp::post())
+            .and(warp::body::bytes())
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, in_format: InoutFormat, out_format: InoutFormat, body: hyper::body::Bytes, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = match in_format.decode_val_from_bytes::<Vec<u64>>(&body) {
+                            Ok(follower_ids) => db.write().await.add_user_followers(auth_key.as_ref(), id, &follower_ids).await
+                                .map_err(WriteFollowersError::Write),
+                            Err(e) => Err(WriteFollowersError::DecodeInput(e))
+                        };
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
+    .or(
+        warp::path!("remove_follower" / u64 / u64 / InoutFormat)
+            .and(auth_key_filter())
+            .then({
+                let db = db.clone();
+                move |id: u64, follower_id: u64, out_format: InoutFormat, auth_key: OptionAuthKey| {
+                    let db = (&db).clone();
+                    async move {
+                        let out = db.write().await.remove_user_follower(auth_key.as_ref(), id, follower_id)
+                            .await
+                            .map_err(RemoveFollowerError::Remove);
+                        out_format.encode_val_to_response(&out)
+                    }
+                }
+            })
+    )
 }
 
 // users/get/json/by_name/griffpatch
 // users/write/json/json
 // users/get_by_name/griffpatch/json
 // users/get_by_id/5432/json
+// users/followers/1882674/json
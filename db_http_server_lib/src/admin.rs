@@ -0,0 +1,8 @@
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum BackupError {
+    // The server was started without a backup directory.
+    Disabled,
+    Backup(db::snapshot::BackupError),
+}
+
+pub type BackupOutput = Result<db::snapshot::Manifest, BackupError>;
@@ -0,0 +1,31 @@
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum GetByIdError {
+    Get(db::comment::GetByIdError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ThreadsError {
+    List(db::comment::ThreadsError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum SearchError {
+    Search(db::comment::SearchError)
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum WriteError {
+    DecodeInput(super::inout_format::DecodeVal),
+    Add(db::comment::AddError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum RemoveByIdError {
+    Remove(db::comment::RemoveByIdError),
+}
+
+pub type GetByIdOutput = Result<db::comment::Value, GetByIdError>;
+pub type ThreadsOutput = Result<Vec<db::comment::Thread>, ThreadsError>;
+pub type SearchOutput = Result<Vec<db::comment::Value>, SearchError>;
+pub type WriteOutput = Result<bool, WriteError>;
+pub type RemoveByIdOutput = Result<bool, RemoveByIdError>;
@@ -0,0 +1,56 @@
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum TopicGetByIdError {
+    Get(db::forum::topic::GetByIdError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ByCategoryError {
+    List(db::forum::topic::ByCategoryError)
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum TopicWriteError {
+    DecodeInput(super::inout_format::DecodeVal),
+    Add(db::forum::topic::AddError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum TopicRemoveByIdError {
+    Remove(db::forum::topic::RemoveByIdError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum PostGetByIdError {
+    Get(db::forum::post::GetByIdError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ByTopicError {
+    List(db::forum::post::ByTopicError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum PostSearchError {
+    Search(db::forum::post::SearchError)
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum PostWriteError {
+    DecodeInput(super::inout_format::DecodeVal),
+    Add(db::forum::post::AddError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum PostRemoveByIdError {
+    Remove(db::forum::post::RemoveByIdError),
+}
+
+pub type TopicGetByIdOutput = Result<db::forum::topic::Value, TopicGetByIdError>;
+pub type ByCategoryOutput = Result<Vec<db::forum::topic::Value>, ByCategoryError>;
+pub type TopicWriteOutput = Result<bool, TopicWriteError>;
+pub type TopicRemoveByIdOutput = Result<bool, TopicRemoveByIdError>;
+pub type PostGetByIdOutput = Result<db::forum::post::Value, PostGetByIdError>;
+pub type ByTopicOutput = Result<Vec<db::forum::post::Value>, ByTopicError>;
+pub type PostSearchOutput = Result<Vec<db::forum::post::Value>, PostSearchError>;
+pub type PostWriteOutput = Result<bool, PostWriteError>;
+pub type PostRemoveByIdOutput = Result<bool, PostRemoveByIdError>;
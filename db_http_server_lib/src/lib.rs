@@ -1,4 +1,9 @@
 pub use inout_format::Value as InoutFormat;
 
 pub mod users;
+pub mod projects;
+pub mod studios;
+pub mod forum;
+pub mod comments;
+pub mod admin;
 pub mod inout_format;
\ No newline at end of file
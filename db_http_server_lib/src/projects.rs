@@ -0,0 +1,30 @@
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum GetByIdError {
+    Get(db::project::GetByIdError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ByAuthorError {
+    Get(db::project::ByAuthorError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum SearchError {
+    Search(db::project::SearchError)
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum WriteError {
+    DecodeInput(super::inout_format::DecodeVal),
+    Add(db::project::AddError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum RemoveByIdError {
+    Remove(db::project::RemoveByIdError),
+}
+
+pub type GetByIdOutput = Result<db::project::Value, GetByIdError>;
+pub type ByAuthorOutput = Result<Vec<db::project::Value>, ByAuthorError>;
+pub type WriteOutput = Result<bool, WriteError>;
+pub type RemoveByIdOutput = Result<bool, RemoveByIdError>;
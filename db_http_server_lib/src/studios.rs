@@ -0,0 +1,39 @@
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum GetByIdError {
+    Get(db::studio::GetByIdError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum SearchError {
+    Search(db::studio::SearchError)
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum WriteError {
+    DecodeInput(super::inout_format::DecodeVal),
+    Add(db::studio::AddError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum RemoveByIdError {
+    Remove(db::studio::RemoveByIdError),
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum WriteListError {
+    DecodeInput(super::inout_format::DecodeVal),
+    Write(db::studio::WriteListError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum RemoveFromListError {
+    Remove(db::studio::WriteListError),
+}
+
+pub type GetByIdOutput = Result<db::studio::Value, GetByIdError>;
+pub type WriteOutput = Result<bool, WriteError>;
+pub type RemoveByIdOutput = Result<bool, RemoveByIdError>;
+pub type MembersOutput = Result<Vec<db::studio::Member>, GetByIdError>;
+pub type ProjectsOutput = Result<Vec<u64>, GetByIdError>;
+pub type WriteListOutput = Result<(), WriteListError>;
+pub type RemoveFromListOutput = Result<bool, RemoveFromListError>;
@@ -6,6 +6,31 @@ pub enum GetByNameError {
     Get(db::user::GetByNameError)
 }
 
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum GetByIdError {
+    Get(db::user::GetByIdError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum PreviousNamesError {
+    Get(db::user::GetByNameError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ProjectsError {
+    Get(db::user::GetByNameError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum HistoryError {
+    Get(db::user::GetByNameError)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ListError {
+    List(db::user::ListError)
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
 pub enum SearchError {
     Search(db::user::SearchError)
@@ -22,9 +47,29 @@ pub enum RemoveByNameError {
     Remove(db::user::RemoveByNameError),
 }
 
+#[derive(Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum WriteFollowersError {
+    DecodeInput(super::inout_format::DecodeVal),
+    Write(db::user::follow::WriteError),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, bincode::Encode, bincode::Decode)]
+pub enum RemoveFollowerError {
+    Remove(db::user::follow::WriteError),
+}
+
 pub type GetByNameOutput = Result<db::user::Value<'static>, GetByNameError>;
+pub type GetByIdOutput = Result<db::user::Value<'static>, GetByIdError>;
+pub type PreviousNamesOutput = Result<Vec<db::Username>, PreviousNamesError>;
+pub type ProjectsOutput = Result<Vec<db::project::Value>, ProjectsError>;
+pub type HistoryOutput = Result<Vec<db::user::history::Entry>, HistoryError>;
+pub type ListOutput = Result<Vec<db::user::Value<'static>>, ListError>;
 pub type WriteOutput = Result<bool, WriteError>;
 pub type RemoveByNameOutput = Result<bool, RemoveByNameError>;
+pub type FollowsOutput = Result<Vec<u64>, ListError>;
+pub type RelationOutput = Result<db::user::follow::Relation, ListError>;
+pub type WriteFollowersOutput = Result<(), WriteFollowersError>;
+pub type RemoveFollowerOutput = Result<bool, RemoveFollowerError>;
 
 // pub struct WriteInput<'a>(String, db::User<'a>);
 